@@ -0,0 +1,150 @@
+//! Request and response bodies of the REST API under `/api/v1/secrets`, shared by the
+//! server and its clients.
+
+use crate::dotenv::EnvVar;
+use crate::envelope::EnvelopeError;
+use crate::payload::{Attachment, Payload};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+/// Body of `POST /api/v1/secrets`: a secret for the server to encrypt.
+#[derive(Serialize, Deserialize)]
+pub struct CreateSecret {
+    pub secret: Zeroizing<String>,
+    #[serde(default)]
+    pub passphrase: Option<Zeroizing<String>>,
+    /// 0 means unlimited.
+    #[serde(default = "default_max_views")]
+    pub max_views: u32,
+    /// Seconds until the secret expires.
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    #[serde(default)]
+    pub format: SecretFormat,
+}
+
+/// How `CreateSecret::secret` is read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretFormat {
+    #[default]
+    Text,
+    /// `KEY=value` lines, validated and stored as variables.
+    Dotenv,
+}
+
+pub fn default_max_views() -> u32 {
+    1
+}
+
+pub fn default_ttl() -> u64 {
+    24 * 60 * 60
+}
+
+/// Returned to the sender after saving: the share link (relative to `/get/`) and the
+/// token that revokes it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedSecret {
+    pub id: String,
+    /// Contains the key, so it is wiped when dropped. Copies made while it is serialised
+    /// or parsed are not.
+    pub link: Zeroizing<String>,
+    pub revoke_token: String,
+}
+
+/// A secret the client encrypted: the id it is bound to and its base64 envelope. Body of
+/// `POST /api/v1/secrets/encrypted` and of `GET /api/v1/secrets/{id}/envelope`.
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    pub id: String,
+    pub envelope: String,
+}
+
+/// Answer to storing an [`EncryptedSecret`]. The link is built by the client, which holds
+/// the key.
+#[derive(Serialize, Deserialize)]
+pub struct StoredSecret {
+    pub id: String,
+    pub revoke_token: String,
+}
+
+/// Body of a successful classic reveal.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum RevealedSecret {
+    Text {
+        secret: Zeroizing<String>,
+    },
+    Dotenv {
+        variables: Vec<EnvVar>,
+    },
+    /// `data` is base64.
+    File {
+        name: String,
+        mime: String,
+        data: Zeroizing<String>,
+    },
+}
+
+impl RevealedSecret {
+    /// `None` for credentials. They are only ever encrypted in the browser, so a server
+    /// that decrypts secrets has none to reveal, and only the browser shows them field
+    /// by field.
+    pub fn from_payload(payload: Payload) -> Option<Self> {
+        match payload {
+            Payload::Text(secret) => Some(RevealedSecret::Text { secret }),
+            Payload::Dotenv(variables) => Some(RevealedSecret::Dotenv { variables }),
+            Payload::File(attachment) => Some(RevealedSecret::File {
+                name: attachment.name,
+                mime: attachment.mime,
+                data: Zeroizing::new(general_purpose::STANDARD.encode(&attachment.data)),
+            }),
+            Payload::Credentials(_) => None,
+        }
+    }
+
+    pub fn into_payload(self) -> Result<Payload, EnvelopeError> {
+        Ok(match self {
+            RevealedSecret::Text { secret } => Payload::Text(secret),
+            RevealedSecret::Dotenv { variables } => Payload::Dotenv(variables),
+            RevealedSecret::File { name, mime, data } => Payload::File(Attachment {
+                name,
+                mime,
+                data: Zeroizing::new(
+                    general_purpose::STANDARD
+                        .decode(data.as_bytes())
+                        .map_err(|_| EnvelopeError::InvalidEncoding)?,
+                ),
+            }),
+        })
+    }
+}
+
+/// What the sender sees on the status page. Never contains the secret or its key.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub created_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub max_views: Option<u32>,
+    /// `None` means unlimited.
+    pub remaining_views: Option<u32>,
+    pub views: u32,
+    /// Unix times of the most recent reveals, oldest first.
+    pub revealed_at: Vec<u64>,
+    pub revoked_at: Option<u64>,
+}
+
+/// Body of every failed request.
+#[derive(Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ErrorDetail {
+    /// Stable, machine-readable: `not_found`, `expired`, `wrong_passphrase`, ...
+    pub code: String,
+    /// For people; may change.
+    pub message: String,
+}
@@ -0,0 +1,272 @@
+use crate::api::{
+    CreateSecret, EncryptedSecret, ErrorBody, RevealedSecret, SavedSecret, StatusReport,
+    StoredSecret,
+};
+use crate::crypto;
+use crate::envelope::{unix_now, Envelope, EnvelopeError, Metadata};
+use crate::link::{LinkError, LinkVersion, ShareLink};
+use crate::payload::Payload;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+/// Talks to a tokenshare server through its REST API. Zero-knowledge secrets are sealed
+/// and opened here, so the server only ever sees their envelopes.
+#[derive(Clone)]
+pub struct Client {
+    server: String,
+    http: reqwest::Client,
+}
+
+/// Policy for a secret sent with [`Client::send`].
+#[derive(Clone)]
+pub struct SendOptions {
+    /// Seconds until the secret expires.
+    pub ttl: u64,
+    /// 0 means unlimited.
+    pub max_views: u32,
+    /// Also required to reveal the secret.
+    pub passphrase: Option<Zeroizing<String>>,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        SendOptions {
+            ttl: crate::api::default_ttl(),
+            max_views: crate::api::default_max_views(),
+            passphrase: None,
+        }
+    }
+}
+
+/// A secret stored with [`Client::send`].
+pub struct Shared {
+    pub id: String,
+    /// The share URL, key included.
+    pub url: Zeroizing<String>,
+    /// The sender's status page, which also revokes the secret.
+    pub status_url: String,
+    pub revoke_token: String,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Link(LinkError),
+    /// The secret has a passphrase and none was given. Checked before anything is
+    /// fetched, since fetching a zero-knowledge secret uses up a view.
+    PassphraseRequired,
+    WrongPassphrase,
+    /// The key in the link does not open the secret.
+    WrongKey,
+    Encryption,
+    /// The server returned an envelope or payload that cannot be read.
+    Corrupt(EnvelopeError),
+    /// The server could not be reached, or gave an answer this client does not understand.
+    Http(reqwest::Error),
+    /// The server refused the request.
+    Api {
+        status: u16,
+        /// Stable, machine-readable: `not_found`, `expired`, `wrong_passphrase`, ...
+        code: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Link(e) => write!(f, "{}", e),
+            ClientError::PassphraseRequired => {
+                write!(f, "This secret is protected by a passphrase.")
+            }
+            ClientError::WrongPassphrase => write!(f, "Wrong passphrase."),
+            ClientError::WrongKey => {
+                write!(f, "The key in this link does not open the secret.")
+            }
+            ClientError::Encryption => write!(f, "Encryption failed."),
+            ClientError::Corrupt(e) => write!(f, "Corrupt secret: {}", e),
+            ClientError::Http(e) => write!(f, "Could not talk to the server: {}", e),
+            ClientError::Api { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<LinkError> for ClientError {
+    fn from(error: LinkError) -> Self {
+        ClientError::Link(error)
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> Self {
+        ClientError::Http(error)
+    }
+}
+
+impl Client {
+    /// `server` is the base URL, e.g. `https://tokenshare.example.com`.
+    pub fn new(server: &str) -> Self {
+        Client {
+            server: server.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Seals `payload` under a new key and stores only the envelope, in zero-knowledge
+    /// mode: the key travels in the fragment of the returned URL.
+    pub async fn send(
+        &self,
+        payload: &Payload,
+        options: &SendOptions,
+    ) -> Result<Shared, ClientError> {
+        let passphrase = options
+            .passphrase
+            .as_ref()
+            .map(|passphrase| passphrase.as_str())
+            .filter(|passphrase| !passphrase.is_empty());
+        let id = Uuid::new_v4().to_string();
+        let metadata = Metadata::new(unix_now(), options.ttl, options.max_views);
+        let sealed = crypto::seal(
+            &id,
+            &payload.encode(),
+            passphrase,
+            metadata,
+            &crypto::DEFAULT_PADDING_BUCKETS,
+        )
+        .map_err(|_| ClientError::Encryption)?;
+        let encrypted = EncryptedSecret {
+            id: id.clone(),
+            envelope: sealed.envelope.to_base64(),
+        };
+        let stored: StoredSecret = call(
+            self.http
+                .post(format!("{}/api/v1/secrets/encrypted", self.server))
+                .json(&encrypted),
+        )
+        .await?;
+        let link = ShareLink {
+            id,
+            key: Zeroizing::new(sealed.key.to_vec()),
+            version: LinkVersion::ZeroKnowledge,
+            passphrase: passphrase.is_some(),
+        };
+        Ok(Shared {
+            url: Zeroizing::new(format!("{}/get/{}", self.server, link)),
+            status_url: format!(
+                "{}/status/{}#{}",
+                self.server, stored.id, stored.revoke_token
+            ),
+            id: stored.id,
+            revoke_token: stored.revoke_token,
+        })
+    }
+
+    /// Has the server encrypt a secret, in classic mode. The returned link is relative to
+    /// `/get/` on this server.
+    pub async fn create(&self, request: &CreateSecret) -> Result<SavedSecret, ClientError> {
+        call(
+            self.http
+                .post(format!("{}/api/v1/secrets", self.server))
+                .json(request),
+        )
+        .await
+    }
+
+    /// Reveals the secret behind a share URL from this server: zero-knowledge secrets
+    /// are decrypted here, classic ones by the server. Pass an empty passphrase if there
+    /// is none.
+    pub async fn reveal(&self, url: &str, passphrase: &str) -> Result<Payload, ClientError> {
+        let (_, link) = ShareLink::from_url(url)?;
+        if link.passphrase && passphrase.is_empty() {
+            return Err(ClientError::PassphraseRequired);
+        }
+        match link.version {
+            LinkVersion::ZeroKnowledge => {
+                let encrypted: EncryptedSecret = call(
+                    self.http
+                        .get(format!("{}/api/v1/secrets/{}/envelope", self.server, link.id)),
+                )
+                .await?;
+                let envelope =
+                    Envelope::from_base64(&encrypted.envelope).map_err(ClientError::Corrupt)?;
+                let plaintext = crypto::open(&link.id, &link.key, &envelope, passphrase)
+                    .map_err(|_| {
+                        if link.passphrase {
+                            ClientError::WrongPassphrase
+                        } else {
+                            ClientError::WrongKey
+                        }
+                    })?;
+                Payload::decode(envelope.version, plaintext).map_err(ClientError::Corrupt)
+            }
+            LinkVersion::Classic => {
+                let path = Zeroizing::new(link.to_string());
+                let revealed: RevealedSecret = call(
+                    self.http
+                        .get(format!("{}/api/v1/secrets/{}", self.server, path.as_str()))
+                        .header("x-passphrase", passphrase),
+                )
+                .await?;
+                revealed.into_payload().map_err(ClientError::Corrupt)
+            }
+        }
+    }
+
+    /// Deletes a secret before it is read.
+    pub async fn revoke(&self, id: &str, revoke_token: &str) -> Result<(), ClientError> {
+        let response = self
+            .http
+            .delete(format!("{}/api/v1/secrets/{}", self.server, id))
+            .bearer_auth(revoke_token)
+            .send()
+            .await?;
+        check(response).await.map(drop)
+    }
+
+    /// When a secret was read, and whether it is still available.
+    pub async fn status(
+        &self,
+        id: &str,
+        revoke_token: &str,
+    ) -> Result<StatusReport, ClientError> {
+        call(
+            self.http
+                .get(format!("{}/api/v1/secrets/{}/status", self.server, id))
+                .bearer_auth(revoke_token),
+        )
+        .await
+    }
+}
+
+/// Sends an API request and parses its JSON answer.
+async fn call<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T, ClientError> {
+    let response = check(request.send().await?).await?;
+    Ok(response.json().await?)
+}
+
+/// Turns error answers into [`ClientError::Api`].
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let (code, message) = match response.json::<ErrorBody>().await {
+        Ok(body) => (body.error.code, body.error.message),
+        Err(_) => (
+            "unexpected".to_string(),
+            format!("The server answered {}", status),
+        ),
+    };
+    Err(ClientError::Api {
+        status: status.as_u16(),
+        code,
+        message,
+    })
+}
@@ -0,0 +1,612 @@
+use crate::envelope::{CipherSuite, Envelope, Kdf, Metadata, VERSION};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::{
+    aead::{
+        generic_array::GenericArray,
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        Aead, KeyInit, OsRng, Payload,
+    },
+    ChaCha20Poly1305, Key, Nonce, XChaCha20Poly1305, XNonce,
+};
+use zeroize::Zeroizing;
+
+/// What every failed encryption or decryption returns; it deliberately says nothing more.
+pub use chacha20poly1305::Error;
+
+/// Length of the key material carried in share URLs.
+pub const KEY_LEN: usize = 32;
+
+/// Length of the random Argon2id salt stored with passphrase-protected secrets.
+pub const SALT_LEN: usize = 16;
+
+/// Sizes, in bytes, that plaintexts are padded up to before encryption, so a stored record
+/// only reveals a coarse size. Larger plaintexts are padded to a multiple of the last one.
+pub const DEFAULT_PADDING_BUCKETS: [usize; 5] = [256, 1024, 4096, 16 * 1024, 64 * 1024];
+
+/// Length of the plaintext length prefix in padded envelopes.
+const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Plaintext bytes per segment of a streamed ciphertext. Only the last segment is shorter.
+pub const SEGMENT_LEN: usize = 64 * 1024;
+
+/// Length of the Poly1305 tag after each segment.
+pub const TAG_LEN: usize = 16;
+
+/// Appended to the key in a share URL when the secret also needs a passphrase, so the
+/// reveal page knows to ask for it before fetching anything.
+pub const PASSPHRASE_MARKER: &str = "::p";
+
+/// Key material, wiped from memory when dropped.
+pub type SecretKey = Zeroizing<[u8; KEY_LEN]>;
+
+/// An envelope ready to be stored, together with the key material for the share URL.
+pub struct Sealed {
+    /// Key material for the share URL. With a passphrase this is not the cipher key itself.
+    pub key: SecretKey,
+    pub envelope: Envelope,
+}
+
+/// Encrypts `plaintext` under a new random key, optionally protected by a passphrase,
+/// binding it to the id it will be stored under and its policy `metadata`. The plaintext
+/// is padded to one of `buckets` first. Used by the server in the classic mode and by
+/// clients (the browser, the CLI) in zero-knowledge mode; see `Sealer` for plaintexts
+/// read in pieces.
+pub fn seal(
+    id: &str,
+    plaintext: &[u8],
+    passphrase: Option<&str>,
+    metadata: Metadata,
+    buckets: &[usize],
+) -> Result<Sealed, chacha20poly1305::Error> {
+    let mut sealer = Sealer::new(id, plaintext.len(), passphrase, metadata, buckets)?;
+    sealer.write(plaintext)?;
+    sealer.finish()
+}
+
+/// Encrypts a plaintext of known length that arrives in pieces, so it never has to be in
+/// memory in full; the ciphertext is still collected in the envelope. Uses
+/// XChaCha20-Poly1305 in the STREAM construction: the padded plaintext is split into
+/// `SEGMENT_LEN` segments, each sealed under a nonce made of a random prefix, its
+/// position and a last-segment flag, so segments cannot be dropped, reordered or cut
+/// off without decryption failing.
+pub struct Sealer {
+    key: SecretKey,
+    envelope: Envelope,
+    aad: Vec<u8>,
+    encryptor: EncryptorBE32<XChaCha20Poly1305>,
+    /// The segment being filled, encrypted in place once the next byte arrives.
+    segment: Zeroizing<Vec<u8>>,
+    len: usize,
+    written: usize,
+    padded_len: usize,
+}
+
+impl Sealer {
+    /// Starts sealing a plaintext of `len` bytes; see `seal` for the other arguments.
+    pub fn new(
+        id: &str,
+        len: usize,
+        passphrase: Option<&str>,
+        metadata: Metadata,
+        buckets: &[usize],
+    ) -> Result<Self, chacha20poly1305::Error> {
+        let len_prefix = u32::try_from(len).map_err(|_| chacha20poly1305::Error)?;
+        let mut key = SecretKey::default();
+        OsRng.fill_bytes(key.as_mut());
+        let kdf = match passphrase {
+            Some(_) => {
+                let mut salt = vec![0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                argon2id(salt)
+            }
+            None => Kdf::None,
+        };
+        let cipher_key = cipher_key(key.as_ref(), passphrase.unwrap_or_default(), &kdf)?;
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(cipher_key.as_ref()));
+        let suite = CipherSuite::XChaCha20Poly1305Stream;
+        let mut nonce = vec![0u8; suite.nonce_len()]; // 152-bit random prefix per message
+        OsRng.fill_bytes(&mut nonce);
+        let encryptor = EncryptorBE32::from_aead(cipher, GenericArray::from_slice(&nonce));
+        let envelope = Envelope {
+            version: VERSION,
+            suite,
+            kdf,
+            nonce,
+            metadata,
+            ciphertext: Vec::with_capacity(sealed_len(len, buckets)),
+        };
+        let aad = envelope.associated_data(id);
+        let mut sealer = Sealer {
+            key,
+            envelope,
+            aad,
+            encryptor,
+            segment: Zeroizing::new(Vec::with_capacity(SEGMENT_LEN + TAG_LEN)),
+            len,
+            written: 0,
+            padded_len: padded_len(len, buckets),
+        };
+        sealer.push(&len_prefix.to_be_bytes())?;
+        Ok(sealer)
+    }
+
+    /// Adds the next piece of the plaintext.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), chacha20poly1305::Error> {
+        if bytes.len() > self.len - self.written {
+            return Err(chacha20poly1305::Error);
+        }
+        self.written += bytes.len();
+        self.push(bytes)
+    }
+
+    /// Pads the plaintext, which must have been written in full, and seals the last
+    /// segment.
+    pub fn finish(mut self) -> Result<Sealed, chacha20poly1305::Error> {
+        if self.written != self.len {
+            return Err(chacha20poly1305::Error);
+        }
+        let mut padding = self.padded_len - LENGTH_PREFIX_LEN - self.len;
+        while padding > 0 {
+            self.seal_full_segment()?;
+            let fill = padding.min(SEGMENT_LEN - self.segment.len());
+            let len = self.segment.len();
+            self.segment.resize(len + fill, 0);
+            padding -= fill;
+        }
+        self.encryptor
+            .encrypt_last_in_place(&self.aad, &mut *self.segment)?;
+        self.envelope.ciphertext.extend_from_slice(&self.segment);
+        Ok(Sealed {
+            key: self.key,
+            envelope: self.envelope,
+        })
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) -> Result<(), chacha20poly1305::Error> {
+        while !bytes.is_empty() {
+            self.seal_full_segment()?;
+            let take = bytes.len().min(SEGMENT_LEN - self.segment.len());
+            self.segment.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+        }
+        Ok(())
+    }
+
+    /// Seals the current segment if it is full. Called only when more plaintext follows,
+    /// since the last segment is sealed differently.
+    fn seal_full_segment(&mut self) -> Result<(), chacha20poly1305::Error> {
+        if self.segment.len() == SEGMENT_LEN {
+            self.encryptor
+                .encrypt_next_in_place(&self.aad, &mut *self.segment)?;
+            self.envelope.ciphertext.extend_from_slice(&self.segment);
+            self.segment.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Decrypts the envelope stored under `id`, rejecting keys and nonces of the wrong length
+/// instead of panicking. Fails if the envelope was moved to another id or its policy was
+/// edited. The passphrase is ignored unless the envelope needs one. The plaintext is
+/// wiped from memory when dropped.
+pub fn open(
+    id: &str,
+    key: &[u8],
+    envelope: &Envelope,
+    passphrase: &str,
+) -> Result<Zeroizing<Vec<u8>>, chacha20poly1305::Error> {
+    let cipher_key = checked_cipher_key(key, envelope, passphrase)?;
+    let aad = envelope.associated_data(id);
+    let payload = Payload {
+        msg: &envelope.ciphertext,
+        aad: &aad,
+    };
+    let plaintext = match envelope.suite {
+        CipherSuite::ChaCha20Poly1305 => Zeroizing::new(
+            ChaCha20Poly1305::new(Key::from_slice(cipher_key.as_ref()))
+                .decrypt(Nonce::from_slice(&envelope.nonce), payload)?,
+        ),
+        CipherSuite::XChaCha20Poly1305 => Zeroizing::new(
+            XChaCha20Poly1305::new(Key::from_slice(cipher_key.as_ref()))
+                .decrypt(XNonce::from_slice(&envelope.nonce), payload)?,
+        ),
+        CipherSuite::XChaCha20Poly1305Stream => {
+            let len = envelope.ciphertext.len();
+            let mut opener = Opener::with_key(&cipher_key, envelope, aad.clone(), len);
+            opener.write(&envelope.ciphertext)?;
+            return opener.finish();
+        }
+    };
+    if envelope.version >= 3 {
+        unpad(plaintext)
+    } else {
+        Ok(plaintext)
+    }
+}
+
+/// Decrypts a streamed ciphertext (see `Sealer`) that arrives in pieces, such as the
+/// chunks of a stored secret, one segment at a time, so the ciphertext never has to be
+/// joined. A wrong key fails on the first segment. The plaintext is built up in full,
+/// since payloads are decoded as a whole.
+pub struct Opener {
+    version: u8,
+    aad: Vec<u8>,
+    decryptor: DecryptorBE32<XChaCha20Poly1305>,
+    /// The segment being filled, decrypted once the next byte arrives.
+    segment: Zeroizing<Vec<u8>>,
+    /// Sized for the whole plaintext up front, so no copy is left behind by growing it.
+    plaintext: Zeroizing<Vec<u8>>,
+    len: usize,
+    read: usize,
+}
+
+impl Opener {
+    /// Starts decrypting the envelope stored under `id`; see `open`. The envelope's own
+    /// ciphertext is ignored, and only the streamed suite can be opened this way. `len` is
+    /// the length of the whole ciphertext, or a bound on it; any more is refused.
+    pub fn new(
+        id: &str,
+        key: &[u8],
+        envelope: &Envelope,
+        passphrase: &str,
+        len: usize,
+    ) -> Result<Self, chacha20poly1305::Error> {
+        if envelope.suite != CipherSuite::XChaCha20Poly1305Stream {
+            return Err(chacha20poly1305::Error);
+        }
+        let cipher_key = checked_cipher_key(key, envelope, passphrase)?;
+        Ok(Opener::with_key(
+            &cipher_key,
+            envelope,
+            envelope.associated_data(id),
+            len,
+        ))
+    }
+
+    fn with_key(cipher_key: &SecretKey, envelope: &Envelope, aad: Vec<u8>, len: usize) -> Self {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(cipher_key.as_ref()));
+        Opener {
+            version: envelope.version,
+            aad,
+            decryptor: DecryptorBE32::from_aead(cipher, GenericArray::from_slice(&envelope.nonce)),
+            segment: Zeroizing::new(Vec::with_capacity(SEGMENT_LEN + TAG_LEN)),
+            plaintext: Zeroizing::new(Vec::with_capacity(len)),
+            len,
+            read: 0,
+        }
+    }
+
+    /// Adds the next piece of the ciphertext.
+    pub fn write(&mut self, mut bytes: &[u8]) -> Result<(), chacha20poly1305::Error> {
+        if bytes.len() > self.len - self.read {
+            return Err(chacha20poly1305::Error);
+        }
+        self.read += bytes.len();
+        while !bytes.is_empty() {
+            if self.segment.len() == SEGMENT_LEN + TAG_LEN {
+                self.decryptor
+                    .decrypt_next_in_place(&self.aad, &mut *self.segment)?;
+                self.plaintext.extend_from_slice(&self.segment);
+                self.segment.clear();
+            }
+            let take = bytes.len().min(SEGMENT_LEN + TAG_LEN - self.segment.len());
+            self.segment.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+        }
+        Ok(())
+    }
+
+    /// Decrypts the last segment, failing if the ciphertext was cut off, and returns the
+    /// plaintext without its padding.
+    pub fn finish(mut self) -> Result<Zeroizing<Vec<u8>>, chacha20poly1305::Error> {
+        if self.segment.is_empty() {
+            return Err(chacha20poly1305::Error);
+        }
+        self.decryptor
+            .decrypt_last_in_place(&self.aad, &mut *self.segment)?;
+        self.plaintext.extend_from_slice(&self.segment);
+        let plaintext = std::mem::take(&mut self.plaintext);
+        if self.version >= 3 {
+            unpad(plaintext)
+        } else {
+            Ok(plaintext)
+        }
+    }
+}
+
+/// The cipher key for `envelope`, rejecting keys and nonces of the wrong length.
+fn checked_cipher_key(
+    key: &[u8],
+    envelope: &Envelope,
+    passphrase: &str,
+) -> Result<SecretKey, chacha20poly1305::Error> {
+    if key.len() != KEY_LEN || envelope.nonce.len() != envelope.suite.nonce_len() {
+        return Err(chacha20poly1305::Error);
+    }
+    cipher_key(key, passphrase, &envelope.kdf)
+}
+
+/// Size of the ciphertext `Sealer` produces for a plaintext of `len` bytes.
+pub fn sealed_len(len: usize, buckets: &[usize]) -> usize {
+    let padded = padded_len(len, buckets);
+    padded + padded.div_ceil(SEGMENT_LEN) * TAG_LEN
+}
+
+/// Size a plaintext of `len` bytes takes up once padded to `buckets`.
+pub fn padded_len(len: usize, buckets: &[usize]) -> usize {
+    let len = LENGTH_PREFIX_LEN + len;
+    match buckets.iter().copied().find(|&bucket| bucket >= len) {
+        Some(bucket) => bucket,
+        None => match buckets.last() {
+            Some(&largest) if largest > 0 => len.div_ceil(largest) * largest,
+            _ => len,
+        },
+    }
+}
+
+/// Strips the length prefix and padding added when sealing, in place.
+fn unpad(mut padded: Zeroizing<Vec<u8>>) -> Result<Zeroizing<Vec<u8>>, chacha20poly1305::Error> {
+    let prefix = padded
+        .get(..LENGTH_PREFIX_LEN)
+        .ok_or(chacha20poly1305::Error)?;
+    let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+    if padded.len() - LENGTH_PREFIX_LEN < len {
+        return Err(chacha20poly1305::Error);
+    }
+    padded.truncate(LENGTH_PREFIX_LEN + len);
+    padded.drain(..LENGTH_PREFIX_LEN);
+    Ok(padded)
+}
+
+/// Argon2id with the default parameters, which new secrets (and legacy records) use.
+pub fn argon2id(salt: Vec<u8>) -> Kdf {
+    let params = Params::default();
+    Kdf::Argon2id {
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt,
+    }
+}
+
+/// Derives the cipher key. For passphrase-protected secrets the URL key material is the
+/// Argon2id secret input, so both the link and the passphrase are needed.
+fn cipher_key(
+    url_key: &[u8],
+    passphrase: &str,
+    kdf: &Kdf,
+) -> Result<SecretKey, chacha20poly1305::Error> {
+    let mut cipher_key = SecretKey::default();
+    let (m_cost, t_cost, p_cost, salt) = match kdf {
+        Kdf::None => {
+            cipher_key.copy_from_slice(url_key);
+            return Ok(cipher_key);
+        }
+        Kdf::Argon2id {
+            m_cost,
+            t_cost,
+            p_cost,
+            salt,
+        } => (*m_cost, *t_cost, *p_cost, salt),
+    };
+    let params =
+        Params::new(m_cost, t_cost, p_cost, Some(KEY_LEN)).map_err(|_| chacha20poly1305::Error)?;
+    let argon2 = Argon2::new_with_secret(url_key, Algorithm::Argon2id, Version::V0x13, params)
+        .map_err(|_| chacha20poly1305::Error)?;
+    argon2
+        .hash_password_into(passphrase.as_bytes(), salt, cipher_key.as_mut())
+        .map_err(|_| chacha20poly1305::Error)?;
+    Ok(cipher_key)
+}
+
+/// Encodes key material for use in a share URL.
+pub fn encode_key(key: &[u8]) -> String {
+    general_purpose::URL_SAFE.encode(key)
+}
+
+/// Decodes key material taken from a share URL.
+pub fn decode_key(encoded: &str) -> Result<Zeroizing<Vec<u8>>, base64::DecodeError> {
+    general_purpose::URL_SAFE
+        .decode(encoded)
+        .map(Zeroizing::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "2f0c5a6e-8d3b-4a51-9f64-1b7e3c2d9a80";
+
+    /// Plaintext lengths on either side of a bucket or segment boundary once the length
+    /// prefix is added, with the padded length each one takes.
+    const BOUNDARIES: [(usize, usize); 6] = [
+        (0, 256),
+        (252, 256),
+        (253, 1024),
+        (65532, 65536),
+        (65533, 131072),
+        (131068, 131072),
+    ];
+
+    fn seal_len(len: usize) -> Sealed {
+        let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        seal(
+            ID,
+            &plaintext,
+            None,
+            Metadata::default(),
+            &DEFAULT_PADDING_BUCKETS,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn opener_never_grows_its_plaintext() {
+        const CHUNK_LEN: usize = 2 * (SEGMENT_LEN + TAG_LEN);
+        let plaintext = vec![5u8; 5 * SEGMENT_LEN];
+        let sealed = seal(ID, &plaintext, None, Metadata::default(), &[]).unwrap();
+        // What the server has: a manifest without ciphertext, and chunks.
+        let chunks: Vec<&[u8]> = sealed.envelope.ciphertext.chunks(CHUNK_LEN).collect();
+        assert_eq!(chunks.len(), 3);
+        let manifest = Envelope {
+            ciphertext: Vec::new(),
+            ..sealed.envelope.clone()
+        };
+        let len = chunks.len() * CHUNK_LEN;
+        let mut opener = Opener::new(ID, sealed.key.as_ref(), &manifest, "", len).unwrap();
+        let capacity = opener.plaintext.capacity();
+        assert!(capacity >= len);
+        for chunk in chunks {
+            opener.write(chunk).unwrap();
+            assert_eq!(opener.plaintext.capacity(), capacity);
+        }
+        let opened = opener.finish().unwrap();
+        assert_eq!(opened.capacity(), capacity);
+        assert_eq!(*opened, plaintext);
+    }
+
+    #[test]
+    fn opener_refuses_more_than_announced() {
+        let sealed = seal(ID, b"secret", None, Metadata::default(), &[]).unwrap();
+        let len = sealed.envelope.ciphertext.len();
+        let mut opener =
+            Opener::new(ID, sealed.key.as_ref(), &sealed.envelope, "", len - 1).unwrap();
+        assert!(opener.write(&sealed.envelope.ciphertext).is_err());
+    }
+
+    #[test]
+    fn padding_sizes() {
+        for (len, padded) in BOUNDARIES {
+            assert_eq!(padded_len(len, &DEFAULT_PADDING_BUCKETS), padded, "{}", len);
+            let segments = padded.div_ceil(SEGMENT_LEN);
+            assert_eq!(
+                sealed_len(len, &DEFAULT_PADDING_BUCKETS),
+                padded + segments * TAG_LEN,
+                "{}",
+                len
+            );
+        }
+        assert_eq!(padded_len(10, &[]), 14);
+        assert_eq!(padded_len(10, &[8]), 16);
+    }
+
+    #[test]
+    fn unpad_checks_the_length_prefix() {
+        let padded = |bytes: &[u8]| Zeroizing::new(bytes.to_vec());
+        assert_eq!(*unpad(padded(&[0, 0, 0, 2, 1, 2, 0, 0])).unwrap(), [1, 2]);
+        assert!(unpad(padded(&[0, 0, 0, 0])).unwrap().is_empty());
+        assert!(unpad(padded(&[0, 0, 0, 5, 1, 2])).is_err());
+        assert!(unpad(padded(&[0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn sealer_round_trips_at_boundaries() {
+        for (len, _) in BOUNDARIES {
+            let sealed = seal_len(len);
+            assert_eq!(
+                sealed.envelope.ciphertext.len(),
+                sealed_len(len, &DEFAULT_PADDING_BUCKETS),
+                "{}",
+                len
+            );
+            let plaintext = open(ID, sealed.key.as_ref(), &sealed.envelope, "").unwrap();
+            assert_eq!(plaintext.len(), len);
+            assert!(plaintext.iter().enumerate().all(|(i, &b)| b == i as u8));
+        }
+    }
+
+    #[test]
+    fn sealer_takes_the_plaintext_in_pieces() {
+        let plaintext = vec![3u8; 2 * SEGMENT_LEN + 1];
+        let mut sealer = Sealer::new(
+            ID,
+            plaintext.len(),
+            Some("passphrase"),
+            Metadata::default(),
+            &DEFAULT_PADDING_BUCKETS,
+        )
+        .unwrap();
+        for piece in plaintext.chunks(1000) {
+            sealer.write(piece).unwrap();
+        }
+        assert!(sealer.write(&[0]).is_err());
+        let sealed = sealer.finish().unwrap();
+        let opened = open(ID, sealed.key.as_ref(), &sealed.envelope, "passphrase").unwrap();
+        assert_eq!(*opened, plaintext);
+        assert!(open(ID, sealed.key.as_ref(), &sealed.envelope, "wrong").is_err());
+    }
+
+    #[test]
+    fn sealer_must_be_written_in_full() {
+        let mut sealer =
+            Sealer::new(ID, 10, None, Metadata::default(), &DEFAULT_PADDING_BUCKETS).unwrap();
+        sealer.write(&[0; 9]).unwrap();
+        assert!(sealer.finish().is_err());
+    }
+
+    #[test]
+    fn tampered_segments_are_rejected() {
+        const SEALED_SEGMENT: usize = SEGMENT_LEN + TAG_LEN;
+        let sealed = seal_len(2 * SEGMENT_LEN);
+        let key = sealed.key.as_ref();
+        let ciphertext = &sealed.envelope.ciphertext;
+        assert_eq!(ciphertext.len(), 3 * SEALED_SEGMENT);
+        let with = |ciphertext: Vec<u8>| Envelope {
+            ciphertext,
+            ..sealed.envelope.clone()
+        };
+
+        // The last segment dropped, or cut short.
+        let dropped = with(ciphertext[..2 * SEALED_SEGMENT].to_vec());
+        assert!(open(ID, key, &dropped, "").is_err());
+        let cut = with(ciphertext[..ciphertext.len() - 1].to_vec());
+        assert!(open(ID, key, &cut, "").is_err());
+        assert!(open(ID, key, &with(Vec::new()), "").is_err());
+
+        // The first two segments swapped.
+        let mut reordered = ciphertext[SEALED_SEGMENT..2 * SEALED_SEGMENT].to_vec();
+        reordered.extend_from_slice(&ciphertext[..SEALED_SEGMENT]);
+        reordered.extend_from_slice(&ciphertext[2 * SEALED_SEGMENT..]);
+        assert!(open(ID, key, &with(reordered), "").is_err());
+
+        // A flipped bit.
+        let mut flipped = ciphertext.clone();
+        flipped[SEALED_SEGMENT + 7] ^= 1;
+        assert!(open(ID, key, &with(flipped), "").is_err());
+    }
+
+    #[test]
+    fn envelopes_are_bound_to_their_id_and_policy() {
+        let sealed = seal_len(100);
+        let key = sealed.key.as_ref();
+        assert!(open(ID, key, &sealed.envelope, "").is_ok());
+        assert!(open(
+            "0b5b8f1e-4c2a-4f7d-8e39-6a1d2c3b4e5f",
+            key,
+            &sealed.envelope,
+            ""
+        )
+        .is_err());
+        let mut extended = sealed.envelope.clone();
+        extended.metadata.ttl = Some(u64::MAX);
+        assert!(open(ID, key, &extended, "").is_err());
+        assert!(open(ID, &key[1..], &sealed.envelope, "").is_err());
+    }
+
+    #[test]
+    fn opener_takes_the_ciphertext_in_pieces() {
+        let plaintext = vec![7u8; 3 * SEGMENT_LEN + 5];
+        let sealed = seal(ID, &plaintext, None, Metadata::default(), &[]).unwrap();
+        for piece in [1, TAG_LEN, SEGMENT_LEN, SEGMENT_LEN + TAG_LEN + 1] {
+            let len = sealed.envelope.ciphertext.len();
+            let mut opener =
+                Opener::new(ID, sealed.key.as_ref(), &sealed.envelope, "", len).unwrap();
+            for bytes in sealed.envelope.ciphertext.chunks(piece) {
+                opener.write(bytes).unwrap();
+            }
+            assert_eq!(*opener.finish().unwrap(), plaintext);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// One `KEY=value` line of a dotenv bundle.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: Zeroizing<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DotenvError {
+    /// 1-based line the problem starts on.
+    pub line: usize,
+    pub kind: DotenvErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DotenvErrorKind {
+    MissingEquals,
+    InvalidKey(String),
+    DuplicateKey(String),
+    UnterminatedQuote,
+    TrailingCharacters,
+    NoVariables,
+}
+
+impl fmt::Display for DotenvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.kind {
+            DotenvErrorKind::MissingEquals => write!(f, "expected KEY=value"),
+            DotenvErrorKind::InvalidKey(key) => write!(
+                f,
+                "\"{}\" is not a valid name; use letters, digits and underscores",
+                key
+            ),
+            DotenvErrorKind::DuplicateKey(key) => write!(f, "{} is set more than once", key),
+            DotenvErrorKind::UnterminatedQuote => write!(f, "the quoted value is never closed"),
+            DotenvErrorKind::TrailingCharacters => {
+                write!(f, "unexpected characters after the closing quote")
+            }
+            DotenvErrorKind::NoVariables => write!(f, "no variables found"),
+        }
+    }
+}
+
+impl std::error::Error for DotenvError {}
+
+/// Parses a dotenv bundle: `KEY=value` lines, optionally prefixed with `export`, with
+/// blank lines and `#` comments ignored. Values may be unquoted (up to an inline ` #`
+/// comment), single-quoted (literal) or double-quoted (with `\n`, `\"` and `\\`
+/// escapes); quoted values may span lines.
+pub fn parse(bundle: &str) -> Result<Vec<EnvVar>, DotenvError> {
+    let mut vars: Vec<EnvVar> = Vec::new();
+    let mut lines = bundle.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line_no = index + 1;
+        let error = |kind| DotenvError {
+            line: line_no,
+            kind,
+        };
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| error(DotenvErrorKind::MissingEquals))?;
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(error(DotenvErrorKind::InvalidKey(key.to_string())));
+        }
+        if vars.iter().any(|var| var.key == key) {
+            return Err(error(DotenvErrorKind::DuplicateKey(key.to_string())));
+        }
+        let rest = rest.trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let mut value = Zeroizing::new(String::new());
+                let mut text = &rest[1..];
+                let after = loop {
+                    match unquote(text, quote, &mut value) {
+                        Some(after) => break after,
+                        None => {
+                            let (_, next) = lines
+                                .next()
+                                .ok_or_else(|| error(DotenvErrorKind::UnterminatedQuote))?;
+                            value.push('\n');
+                            text = next;
+                        }
+                    }
+                };
+                let after = after.trim_start();
+                if !after.is_empty() && !after.starts_with('#') {
+                    return Err(error(DotenvErrorKind::TrailingCharacters));
+                }
+                value
+            }
+            _ => {
+                let value = rest.split_once(" #").map_or(rest, |(value, _)| value);
+                Zeroizing::new(value.trim_end().to_string())
+            }
+        };
+        vars.push(EnvVar {
+            key: key.to_string(),
+            value,
+        });
+    }
+    if vars.is_empty() {
+        return Err(DotenvError {
+            line: 1,
+            kind: DotenvErrorKind::NoVariables,
+        });
+    }
+    Ok(vars)
+}
+
+/// Appends `text` up to the closing `quote` to `value`, returning what follows the quote,
+/// or `None` if the quote is not closed on this line.
+fn unquote<'a>(text: &'a str, quote: char, value: &mut String) -> Option<&'a str> {
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if c == quote => return Some(&text[index + 1..]),
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, escaped)) => value.push(escaped),
+                None => value.push('\\'),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Whether `key` can name a variable: a letter or `_`, then letters, digits, `_` or `.`.
+pub fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Writes the variables back as a `.env` file, quoting values where needed.
+pub fn to_dotenv(vars: &[EnvVar]) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::new());
+    for var in vars {
+        out.push_str(&var.key);
+        out.push('=');
+        let plain = var.value.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':' | '@' | '+' | ',')
+        });
+        if plain {
+            out.push_str(&var.value);
+        } else {
+            out.push('"');
+            push_escaped(&mut out, &var.value, false);
+            out.push('"');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Writes the variables as a file for `docker run --env-file`, which takes every value
+/// literally up to the end of its line. Multi-line values cannot be written, so they
+/// are left out with a comment saying so.
+pub fn to_docker_env_file(vars: &[EnvVar]) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(String::new());
+    for var in vars {
+        if var.value.contains(['\n', '\r']) {
+            out.push_str(&format!(
+                "# {} left out: --env-file does not support multi-line values\n",
+                var.key
+            ));
+            continue;
+        }
+        out.push_str(&var.key);
+        out.push('=');
+        out.push_str(&var.value);
+        out.push('\n');
+    }
+    out
+}
+
+/// Writes the variables as a Kubernetes `Secret` manifest named `name`, with the values
+/// under `stringData` as double-quoted YAML strings.
+pub fn to_kubernetes_secret(vars: &[EnvVar], name: &str) -> Zeroizing<String> {
+    let mut out = Zeroizing::new(format!(
+        "apiVersion: v1\nkind: Secret\nmetadata:\n  name: {}\ntype: Opaque\nstringData:\n",
+        name
+    ));
+    for var in vars {
+        out.push_str("  ");
+        out.push_str(&var.key);
+        out.push_str(": \"");
+        push_escaped(&mut out, &var.value, true);
+        out.push_str("\"\n");
+    }
+    out
+}
+
+/// Escapes `value` for a double-quoted string: backslashes, quotes and line breaks, and
+/// with `control` other control characters too (as YAML requires).
+fn push_escaped(out: &mut String, value: &str, control: bool) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' if control => out.push_str("\\t"),
+            c if control && c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(vars: &[EnvVar]) -> Vec<(&str, &str)> {
+        vars.iter()
+            .map(|var| (var.key.as_str(), var.value.as_str()))
+            .collect()
+    }
+
+    fn error(bundle: &str) -> DotenvError {
+        parse(bundle).err().expect("bundle should be rejected")
+    }
+
+    #[test]
+    fn plain_lines() {
+        let vars = parse("# comment\n\nA=1\nexport B = two words \n  C=\nD.E_f=x=y\n").unwrap();
+        assert_eq!(
+            pairs(&vars),
+            [("A", "1"), ("B", "two words"), ("C", ""), ("D.E_f", "x=y")]
+        );
+    }
+
+    #[test]
+    fn inline_comments() {
+        let vars = parse("A=1 # one\nB=a#b\nC=\"2\" # two\nD='#' #\n").unwrap();
+        assert_eq!(
+            pairs(&vars),
+            [("A", "1"), ("B", "a#b"), ("C", "2"), ("D", "#")]
+        );
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        let bundle = r##"A="a\nb\t\"c\" \\ \$"
+B='a\nb "c"'
+C="\"""##;
+        let vars = parse(bundle).unwrap();
+        assert_eq!(
+            pairs(&vars),
+            [("A", "a\nb\t\"c\" \\ $"), ("B", "a\\nb \"c\""), ("C", "\"")]
+        );
+    }
+
+    #[test]
+    fn multi_line_quotes() {
+        let bundle = "KEY=\"-----BEGIN KEY-----\nabc\n-----END KEY-----\"\nNEXT='x\n\ny'\nLAST=1";
+        let vars = parse(bundle).unwrap();
+        assert_eq!(
+            pairs(&vars),
+            [
+                ("KEY", "-----BEGIN KEY-----\nabc\n-----END KEY-----"),
+                ("NEXT", "x\n\ny"),
+                ("LAST", "1")
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        let at = |line, kind| DotenvError { line, kind };
+        assert_eq!(error("A=1\nB"), at(2, DotenvErrorKind::MissingEquals));
+        assert_eq!(
+            error("1A=x"),
+            at(1, DotenvErrorKind::InvalidKey("1A".into()))
+        );
+        assert_eq!(
+            error("A=1\n\nA=2"),
+            at(3, DotenvErrorKind::DuplicateKey("A".into()))
+        );
+        assert_eq!(
+            error("A=1\nB=\"open\nstill open"),
+            at(2, DotenvErrorKind::UnterminatedQuote)
+        );
+        assert_eq!(error("A='x' y"), at(1, DotenvErrorKind::TrailingCharacters));
+        assert_eq!(error("# only\n\n"), at(1, DotenvErrorKind::NoVariables));
+    }
+
+    #[test]
+    fn to_dotenv_round_trips() {
+        let bundle =
+            "A=plain\nB=\"two words\"\nC='single \\n'\nD=\"line\\nbreak \\\"q\\\" \\\\\"\nE=\n";
+        let vars = parse(bundle).unwrap();
+        let written = to_dotenv(&vars);
+        assert_eq!(pairs(&parse(&written).unwrap()), pairs(&vars));
+    }
+
+    #[test]
+    fn docker_env_files_leave_out_multi_line_values() {
+        let vars = parse("A=1\nB=\"x\ny\"").unwrap();
+        assert_eq!(
+            *to_docker_env_file(&vars),
+            "A=1\n# B left out: --env-file does not support multi-line values\n"
+        );
+    }
+}
@@ -0,0 +1,611 @@
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use std::fmt;
+use std::io::Write;
+
+/// Leading bytes of every encoded envelope. Legacy records are JSON and start with `{`.
+const MAGIC: &[u8] = b"TS";
+
+/// Version written by this build. Records of older versions stay readable.
+///
+/// * 1: ciphertext without associated data (also used for converted legacy records)
+/// * 2: ciphertext bound to the record id and policy metadata as associated data
+/// * 3: as 2, with the plaintext length-prefixed and padded to a size bucket
+/// * 4: as 3, with the plaintext a typed payload (text or a file, see `payload.rs`)
+pub const VERSION: u8 = 4;
+
+const KDF_NONE: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+
+const META_CREATED_AT: u8 = 1;
+const META_TTL: u8 = 2;
+const META_REMAINING_VIEWS: u8 = 3;
+const META_MAX_VIEWS: u8 = 4;
+const META_REVOKE_HASH: u8 = 5;
+const META_FAILED_ATTEMPTS: u8 = 6;
+const META_LAST_FAILED_AT: u8 = 7;
+const META_CHUNKS: u8 = 8;
+const META_KEY_CHECK: u8 = 9;
+
+/// AEAD used to encrypt the payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherSuite {
+    /// ChaCha20-Poly1305 with a 96-bit random nonce. Only read, for older records.
+    ChaCha20Poly1305,
+    /// XChaCha20-Poly1305 with a 192-bit random nonce, safe to generate at random even
+    /// when a key is reused. Only read, for older records.
+    XChaCha20Poly1305,
+    /// XChaCha20-Poly1305 in the STREAM construction (32-bit big-endian segment counter
+    /// and last-segment flag) with a 152-bit random nonce prefix, so large payloads are
+    /// sealed and opened segment by segment.
+    XChaCha20Poly1305Stream,
+}
+
+impl CipherSuite {
+    fn id(self) -> u8 {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 1,
+            CipherSuite::XChaCha20Poly1305 => 2,
+            CipherSuite::XChaCha20Poly1305Stream => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(CipherSuite::ChaCha20Poly1305),
+            2 => Some(CipherSuite::XChaCha20Poly1305),
+            3 => Some(CipherSuite::XChaCha20Poly1305Stream),
+            _ => None,
+        }
+    }
+
+    pub fn nonce_len(self) -> usize {
+        match self {
+            CipherSuite::ChaCha20Poly1305 => 12,
+            CipherSuite::XChaCha20Poly1305 => 24,
+            CipherSuite::XChaCha20Poly1305Stream => 19,
+        }
+    }
+}
+
+/// How the cipher key is obtained from the key material in the share URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    /// The URL key is the cipher key.
+    None,
+    /// Argon2id over the passphrase, keyed with the URL key.
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+        salt: Vec<u8>,
+    },
+}
+
+/// Plaintext policy data kept next to the ciphertext. Unknown entries are skipped when
+/// decoding, so newer builds can add entries without breaking older records.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Unix time in seconds.
+    pub created_at: Option<u64>,
+    /// Lifetime in seconds, counted from `created_at`.
+    pub ttl: Option<u64>,
+    /// Views left before the record is deleted; `None` means unlimited. Changes after the
+    /// secret is stored, like the failed attempt entries.
+    pub remaining_views: Option<u32>,
+    /// View limit chosen by the sender; `None` means unlimited.
+    pub max_views: Option<u32>,
+    /// SHA-256 of the sender's revoke token. Set by the server, never sent to recipients.
+    pub revoke_hash: Option<Vec<u8>>,
+    /// Wrong passphrases tried against the server so far, counted only for links that
+    /// passed `key_check`.
+    pub failed_attempts: Option<u32>,
+    /// Unix time of the last wrong passphrase.
+    pub last_failed_at: Option<u64>,
+    /// Number of store entries the ciphertext is split across when it is too large for
+    /// one. The stored record then has an empty ciphertext. Set by the server.
+    pub chunks: Option<u32>,
+    /// Hash of the URL key of a secret the server encrypted, so that a link can be checked
+    /// before decrypting. Set by the server, never sent to recipients.
+    pub key_check: Option<Vec<u8>>,
+}
+
+/// A stored secret: everything needed to decrypt it except the key from the share URL
+/// (and the passphrase, if any).
+///
+/// Encoded as `"TS" | version | suite | kdf [| m_cost | t_cost | p_cost | salt_len | salt]
+/// | nonce_len | nonce | metadata_len | metadata | ciphertext`, with integers big-endian
+/// and metadata as `tag | len (u16) | value` entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u8,
+    pub suite: CipherSuite,
+    pub kdf: Kdf,
+    pub nonce: Vec<u8>,
+    pub metadata: Metadata,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    InvalidEncoding,
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownSuite(u8),
+    UnknownKdf(u8),
+    UnknownPayload(u8),
+    InvalidNonce,
+    Truncated,
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::InvalidEncoding => write!(f, "invalid base64"),
+            EnvelopeError::BadMagic => write!(f, "not an envelope"),
+            EnvelopeError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            EnvelopeError::UnknownSuite(s) => write!(f, "unknown cipher suite {}", s),
+            EnvelopeError::UnknownKdf(k) => write!(f, "unknown key derivation {}", k),
+            EnvelopeError::UnknownPayload(p) => write!(f, "unknown payload kind {}", p),
+            EnvelopeError::InvalidNonce => write!(f, "invalid nonce length"),
+            EnvelopeError::Truncated => write!(f, "truncated envelope"),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl Envelope {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.encode_header();
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+
+    /// Encodes everything but the ciphertext, for records whose ciphertext is stored in
+    /// chunks. Decodes as an envelope with an empty ciphertext.
+    pub fn encode_header(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        out.push(self.suite.id());
+        match &self.kdf {
+            Kdf::None => out.push(KDF_NONE),
+            Kdf::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+                salt,
+            } => {
+                out.push(KDF_ARGON2ID);
+                out.extend_from_slice(&m_cost.to_be_bytes());
+                out.extend_from_slice(&t_cost.to_be_bytes());
+                out.extend_from_slice(&p_cost.to_be_bytes());
+                out.push(salt.len() as u8);
+                out.extend_from_slice(salt);
+            }
+        }
+        out.push(self.nonce.len() as u8);
+        out.extend_from_slice(&self.nonce);
+        let metadata = self.metadata.encode();
+        out.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&metadata);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(EnvelopeError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(version));
+        }
+        let suite = reader.u8()?;
+        let suite = CipherSuite::from_id(suite).ok_or(EnvelopeError::UnknownSuite(suite))?;
+        let kdf = match reader.u8()? {
+            KDF_NONE => Kdf::None,
+            KDF_ARGON2ID => Kdf::Argon2id {
+                m_cost: reader.u32()?,
+                t_cost: reader.u32()?,
+                p_cost: reader.u32()?,
+                salt: {
+                    let len = reader.u8()? as usize;
+                    reader.take(len)?.to_vec()
+                },
+            },
+            kdf => return Err(EnvelopeError::UnknownKdf(kdf)),
+        };
+        let nonce_len = reader.u8()? as usize;
+        if nonce_len != suite.nonce_len() {
+            return Err(EnvelopeError::InvalidNonce);
+        }
+        let nonce = reader.take(nonce_len)?.to_vec();
+        let metadata_len = reader.u16()? as usize;
+        let metadata = Metadata::decode(reader.take(metadata_len)?)?;
+        Ok(Envelope {
+            version,
+            suite,
+            kdf,
+            nonce,
+            metadata,
+            ciphertext: reader.0.to_vec(),
+        })
+    }
+
+    /// Associated data the ciphertext is bound to: the envelope header, the id the record
+    /// is stored under and the policy chosen by the sender. Moving a record to another id
+    /// or editing its policy makes decryption fail. Empty for version 1 envelopes.
+    pub fn associated_data(&self, id: &str) -> Vec<u8> {
+        if self.version < 2 {
+            return Vec::new();
+        }
+        let policy = Metadata {
+            created_at: self.metadata.created_at,
+            ttl: self.metadata.ttl,
+            max_views: self.metadata.max_views,
+            ..Metadata::default()
+        };
+        let mut aad = Vec::new();
+        aad.extend_from_slice(MAGIC);
+        aad.push(self.version);
+        aad.push(self.suite.id());
+        aad.extend_from_slice(&(id.len() as u16).to_be_bytes());
+        aad.extend_from_slice(id.as_bytes());
+        aad.extend_from_slice(&policy.encode());
+        aad
+    }
+
+    /// Whether the server encrypted this secret (classic mode), going by the key check it
+    /// keeps for those. Such envelopes must only be opened by the server, which limits
+    /// passphrase guesses; anyone holding one could guess offline.
+    pub fn is_server_encrypted(&self) -> bool {
+        self.metadata.key_check.is_some()
+    }
+
+    /// Encodes the envelope for transport through server functions, without joining the
+    /// header and ciphertext first.
+    pub fn to_base64(&self) -> String {
+        let mut encoder =
+            base64::write::EncoderStringWriter::new(&general_purpose::URL_SAFE_NO_PAD);
+        for part in [&self.encode_header(), &self.ciphertext] {
+            encoder
+                .write_all(part)
+                .expect("writing to a String cannot fail");
+        }
+        encoder.into_inner()
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, EnvelopeError> {
+        let bytes = general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|_| EnvelopeError::InvalidEncoding)?;
+        Self::decode(&bytes)
+    }
+}
+
+/// Records stored as JSON before the binary envelope existed. They are still read, and
+/// converted to envelopes on the fly.
+#[derive(Deserialize)]
+pub struct LegacyRecord {
+    nonce: Vec<u8>,
+    secret: Vec<u8>,
+    #[serde(default)]
+    salt: Option<Vec<u8>>,
+    /// Records written before view counts existed are one-time secrets.
+    #[serde(default = "one_view")]
+    remaining_views: Option<u32>,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    ttl: Option<u64>,
+}
+
+fn one_view() -> Option<u32> {
+    Some(1)
+}
+
+impl From<LegacyRecord> for Envelope {
+    fn from(record: LegacyRecord) -> Self {
+        let kdf = match record.salt {
+            // Legacy records were always derived with the default Argon2id parameters.
+            Some(salt) => crate::crypto::argon2id(salt),
+            None => Kdf::None,
+        };
+        Envelope {
+            // Legacy records carry no associated data.
+            version: 1,
+            suite: CipherSuite::ChaCha20Poly1305,
+            kdf,
+            nonce: record.nonce,
+            metadata: Metadata {
+                created_at: record.created_at,
+                ttl: record.ttl,
+                remaining_views: record.remaining_views,
+                ..Metadata::default()
+            },
+            ciphertext: record.secret,
+        }
+    }
+}
+
+impl Metadata {
+    /// Policy of a new secret; `max_views` of 0 means unlimited.
+    pub fn new(created_at: u64, ttl: u64, max_views: u32) -> Self {
+        let max_views = (max_views > 0).then_some(max_views);
+        Metadata {
+            created_at: Some(created_at),
+            ttl: Some(ttl),
+            remaining_views: max_views,
+            max_views,
+            ..Metadata::default()
+        }
+    }
+
+    /// Drops what only the server keeps, before the envelope is handed to a client.
+    pub fn clear_private(&mut self) {
+        self.revoke_hash = None;
+        self.failed_attempts = None;
+        self.last_failed_at = None;
+        self.chunks = None;
+        self.key_check = None;
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        match (self.created_at, self.ttl) {
+            (Some(created_at), Some(ttl)) => now >= created_at.saturating_add(ttl),
+            _ => false,
+        }
+    }
+
+    /// Whether `max_attempts` wrong passphrases have been tried and the last one was less
+    /// than `lockout` seconds ago.
+    pub fn is_locked_out(&self, now: u64, max_attempts: u32, lockout: u64) -> bool {
+        match (self.failed_attempts, self.last_failed_at) {
+            (Some(attempts), Some(last)) => {
+                attempts >= max_attempts && now < last.saturating_add(lockout)
+            }
+            _ => false,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut entry = |tag: u8, value: &[u8]| {
+            out.push(tag);
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value);
+        };
+        if let Some(created_at) = self.created_at {
+            entry(META_CREATED_AT, &created_at.to_be_bytes());
+        }
+        if let Some(ttl) = self.ttl {
+            entry(META_TTL, &ttl.to_be_bytes());
+        }
+        if let Some(remaining_views) = self.remaining_views {
+            entry(META_REMAINING_VIEWS, &remaining_views.to_be_bytes());
+        }
+        if let Some(max_views) = self.max_views {
+            entry(META_MAX_VIEWS, &max_views.to_be_bytes());
+        }
+        if let Some(revoke_hash) = &self.revoke_hash {
+            entry(META_REVOKE_HASH, revoke_hash);
+        }
+        if let Some(failed_attempts) = self.failed_attempts {
+            entry(META_FAILED_ATTEMPTS, &failed_attempts.to_be_bytes());
+        }
+        if let Some(last_failed_at) = self.last_failed_at {
+            entry(META_LAST_FAILED_AT, &last_failed_at.to_be_bytes());
+        }
+        if let Some(chunks) = self.chunks {
+            entry(META_CHUNKS, &chunks.to_be_bytes());
+        }
+        if let Some(key_check) = &self.key_check {
+            entry(META_KEY_CHECK, key_check);
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        let mut reader = Reader(bytes);
+        let mut metadata = Metadata::default();
+        while !reader.0.is_empty() {
+            let tag = reader.u8()?;
+            let len = reader.u16()? as usize;
+            let mut value = Reader(reader.take(len)?);
+            match tag {
+                META_CREATED_AT => metadata.created_at = Some(value.u64()?),
+                META_TTL => metadata.ttl = Some(value.u64()?),
+                META_REMAINING_VIEWS => metadata.remaining_views = Some(value.u32()?),
+                META_MAX_VIEWS => metadata.max_views = Some(value.u32()?),
+                META_REVOKE_HASH => metadata.revoke_hash = Some(value.0.to_vec()),
+                META_FAILED_ATTEMPTS => metadata.failed_attempts = Some(value.u32()?),
+                META_LAST_FAILED_AT => metadata.last_failed_at = Some(value.u64()?),
+                META_CHUNKS => metadata.chunks = Some(value.u32()?),
+                META_KEY_CHECK => metadata.key_check = Some(value.0.to_vec()),
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// Current Unix time in seconds, for metadata timestamps.
+pub fn unix_now() -> u64 {
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        (js_sys::Date::now() / 1000.0) as u64
+    }
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the big-endian fields of envelopes and payloads.
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], EnvelopeError> {
+        if self.0.len() < len {
+            return Err(EnvelopeError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, EnvelopeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, EnvelopeError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, EnvelopeError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, EnvelopeError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(kdf: Kdf) -> Envelope {
+        Envelope {
+            version: VERSION,
+            suite: CipherSuite::XChaCha20Poly1305Stream,
+            kdf,
+            nonce: vec![7; CipherSuite::XChaCha20Poly1305Stream.nonce_len()],
+            metadata: Metadata {
+                revoke_hash: Some(vec![1, 2, 3]),
+                failed_attempts: Some(2),
+                last_failed_at: Some(1_700_000_100),
+                chunks: Some(3),
+                key_check: Some(vec![4; 32]),
+                ..Metadata::new(1_700_000_000, 3600, 5)
+            },
+            ciphertext: b"ciphertext".to_vec(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for kdf in [Kdf::None, crate::crypto::argon2id(vec![9; 16])] {
+            let envelope = envelope(kdf);
+            assert_eq!(Envelope::decode(&envelope.encode()), Ok(envelope.clone()));
+            assert_eq!(Envelope::from_base64(&envelope.to_base64()), Ok(envelope));
+        }
+    }
+
+    #[test]
+    fn header_decodes_without_ciphertext() {
+        let envelope = envelope(Kdf::None);
+        let header = Envelope::decode(&envelope.encode_header()).unwrap();
+        assert!(header.ciphertext.is_empty());
+        assert_eq!(header.metadata, envelope.metadata);
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let envelope = envelope(crate::crypto::argon2id(vec![9; 16]));
+        let header = envelope.encode_header();
+        for len in 0..header.len() {
+            let result = Envelope::decode(&header[..len]);
+            assert!(result.is_err(), "decoded {} of {} bytes", len, header.len());
+        }
+        assert_eq!(Envelope::decode(b"T"), Err(EnvelopeError::Truncated));
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let encoded = envelope(Kdf::None).encode();
+        let with = |index: usize, value: u8| {
+            let mut bytes = encoded.clone();
+            bytes[index] = value;
+            Envelope::decode(&bytes)
+        };
+        assert_eq!(with(0, b'{'), Err(EnvelopeError::BadMagic));
+        assert_eq!(with(2, 0), Err(EnvelopeError::UnsupportedVersion(0)));
+        assert_eq!(
+            with(2, VERSION + 1),
+            Err(EnvelopeError::UnsupportedVersion(VERSION + 1))
+        );
+        assert_eq!(with(3, 9), Err(EnvelopeError::UnknownSuite(9)));
+        assert_eq!(with(4, 9), Err(EnvelopeError::UnknownKdf(9)));
+        assert_eq!(with(5, 12), Err(EnvelopeError::InvalidNonce));
+        assert_eq!(
+            Envelope::from_base64("not base64!"),
+            Err(EnvelopeError::InvalidEncoding)
+        );
+    }
+
+    #[test]
+    fn unknown_metadata_tags_are_skipped() {
+        let metadata = Metadata::new(1_700_000_000, 3600, 1);
+        let mut encoded = vec![200, 0, 3, 1, 2, 3];
+        encoded.extend_from_slice(&metadata.encode());
+        encoded.extend_from_slice(&[201, 0, 0]);
+        assert_eq!(Metadata::decode(&encoded), Ok(metadata));
+        // An unknown entry still has to be complete.
+        assert_eq!(
+            Metadata::decode(&[200, 0, 3, 1]),
+            Err(EnvelopeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn private_metadata_is_cleared() {
+        let mut envelope = envelope(Kdf::None);
+        envelope.metadata.clear_private();
+        assert_eq!(
+            envelope.metadata,
+            Metadata::new(1_700_000_000, 3600, 5),
+            "only the policy is left"
+        );
+    }
+
+    #[test]
+    fn server_encrypted_envelopes_are_told_apart() {
+        assert!(envelope(Kdf::None).is_server_encrypted());
+        let mut zero_knowledge = envelope(crate::crypto::argon2id(vec![9; 16]));
+        zero_knowledge.metadata.key_check = None;
+        assert!(!zero_knowledge.is_server_encrypted());
+        // The key check survives storage, or classic records would be handed out later.
+        let stored = Envelope::decode(&envelope(Kdf::None).encode_header()).unwrap();
+        assert!(stored.is_server_encrypted());
+    }
+
+    #[test]
+    fn legacy_records_convert() {
+        let record: LegacyRecord =
+            serde_json::from_str(r#"{"nonce": [1, 2], "secret": [3, 4], "salt": [5]}"#).unwrap();
+        let envelope = Envelope::from(record);
+        assert_eq!(envelope.version, 1);
+        assert_eq!(envelope.suite, CipherSuite::ChaCha20Poly1305);
+        assert_eq!(envelope.kdf, crate::crypto::argon2id(vec![5]));
+        assert_eq!(envelope.nonce, vec![1, 2]);
+        assert_eq!(envelope.ciphertext, vec![3, 4]);
+        // Written before view counts existed, so a one-time secret.
+        assert_eq!(envelope.metadata.remaining_views, Some(1));
+        assert!(envelope.associated_data("id").is_empty());
+
+        let record: LegacyRecord = serde_json::from_str(
+            r#"{"nonce": [], "secret": [], "remaining_views": null, "created_at": 10, "ttl": 20}"#,
+        )
+        .unwrap();
+        let envelope = Envelope::from(record);
+        assert_eq!(envelope.kdf, Kdf::None);
+        assert_eq!(envelope.metadata.remaining_views, None);
+        assert!(envelope.metadata.is_expired(30));
+    }
+}
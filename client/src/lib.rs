@@ -0,0 +1,17 @@
+//! Everything a tokenshare client needs: the envelope format, share links, encryption,
+//! payloads and the REST API types, for native Rust and `wasm32` alike. The web app, its
+//! server and the CLI all build on this crate.
+//!
+//! With the `http` feature, [`Client`] talks to a server through its REST API.
+
+pub mod api;
+#[cfg(feature = "http")]
+mod client;
+pub mod crypto;
+pub mod dotenv;
+pub mod envelope;
+pub mod link;
+pub mod payload;
+
+#[cfg(feature = "http")]
+pub use client::{Client, ClientError, SendOptions, Shared};
@@ -0,0 +1,209 @@
+use crate::crypto;
+use std::fmt;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+/// How the key travels in a share link, which also decides who decrypts the secret.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkVersion {
+    /// `/get/{id}::{key}`: the key is part of the path and the server decrypts.
+    Classic,
+    /// `/get/{id}#{key}`: the key is in the fragment, which only the browser sees.
+    ZeroKnowledge,
+}
+
+/// The part of a share URL after `/get/`, parsed and validated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShareLink {
+    pub id: String,
+    pub key: Zeroizing<Vec<u8>>,
+    pub version: LinkVersion,
+    /// The secret also needs a passphrase, so the reveal page has to ask for one.
+    pub passphrase: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    /// A whole URL was expected, with `/get/` in it.
+    NotAShareLink,
+    MissingKey,
+    InvalidId,
+    InvalidKeyEncoding,
+    InvalidKeyLength(usize),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::NotAShareLink => write!(f, "This is not a share link."),
+            LinkError::MissingKey => write!(
+                f,
+                "This link is missing its key. Make sure you copied the whole link."
+            ),
+            LinkError::InvalidId => write!(f, "This link does not point to a secret."),
+            LinkError::InvalidKeyEncoding => write!(
+                f,
+                "The key in this link is damaged. Make sure you copied the whole link."
+            ),
+            LinkError::InvalidKeyLength(_) => write!(
+                f,
+                "The key in this link has the wrong length. Make sure you copied the whole link."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl ShareLink {
+    /// Parses the `:id` route parameter and the URL fragment (without `#`, empty when the
+    /// link has none). Classic links carry the key in `path`, zero-knowledge links in
+    /// `fragment`.
+    pub fn parse(path: &str, fragment: &str) -> Result<Self, LinkError> {
+        let (id, key, version) = match path.split_once("::") {
+            Some((id, key)) => (id, key, LinkVersion::Classic),
+            None if fragment.is_empty() => return Err(LinkError::MissingKey),
+            None => (path, fragment, LinkVersion::ZeroKnowledge),
+        };
+        Uuid::parse_str(id).map_err(|_| LinkError::InvalidId)?;
+        let (key, passphrase) = match key.strip_suffix(crypto::PASSPHRASE_MARKER) {
+            Some(key) => (key, true),
+            None => (key, false),
+        };
+        if key.is_empty() {
+            return Err(LinkError::MissingKey);
+        }
+        let key = crypto::decode_key(key).map_err(|_| LinkError::InvalidKeyEncoding)?;
+        if key.len() != crypto::KEY_LEN {
+            return Err(LinkError::InvalidKeyLength(key.len()));
+        }
+        Ok(ShareLink {
+            id: id.to_string(),
+            key,
+            version,
+            passphrase,
+        })
+    }
+
+    /// Parses a whole share URL, returning the server it points to (everything before
+    /// `/get/`) along with the link.
+    pub fn from_url(url: &str) -> Result<(&str, Self), LinkError> {
+        let (url, fragment) = url.split_once('#').unwrap_or((url, ""));
+        let (server, path) = url.split_once("/get/").ok_or(LinkError::NotAShareLink)?;
+        Ok((server, ShareLink::parse(path, fragment)?))
+    }
+}
+
+/// Formats the link as it appears after `/get/`.
+impl fmt::Display for ShareLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = match self.version {
+            LinkVersion::Classic => "::",
+            LinkVersion::ZeroKnowledge => "#",
+        };
+        let marker = if self.passphrase {
+            crypto::PASSPHRASE_MARKER
+        } else {
+            ""
+        };
+        write!(
+            f,
+            "{}{}{}{}",
+            self.id,
+            separator,
+            crypto::encode_key(&self.key),
+            marker
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "00000000-0000-4000-8000-000000000000";
+
+    fn key() -> String {
+        crypto::encode_key(&[7; crypto::KEY_LEN])
+    }
+
+    #[test]
+    fn classic_links() {
+        let link = ShareLink::parse(&format!("{}::{}", ID, key()), "").unwrap();
+        assert_eq!(link.id, ID);
+        assert_eq!(link.key.as_slice(), &[7; crypto::KEY_LEN]);
+        assert_eq!(link.version, LinkVersion::Classic);
+        assert!(!link.passphrase);
+        assert_eq!(link.to_string(), format!("{}::{}", ID, key()));
+
+        let link = ShareLink::parse(&format!("{}::{}::p", ID, key()), "").unwrap();
+        assert_eq!(link.version, LinkVersion::Classic);
+        assert!(link.passphrase);
+        assert_eq!(link.to_string(), format!("{}::{}::p", ID, key()));
+    }
+
+    #[test]
+    fn zero_knowledge_links() {
+        let link = ShareLink::parse(ID, &key()).unwrap();
+        assert_eq!(link.version, LinkVersion::ZeroKnowledge);
+        assert!(!link.passphrase);
+        assert_eq!(link.to_string(), format!("{}#{}", ID, key()));
+
+        let link = ShareLink::parse(ID, &format!("{}::p", key())).unwrap();
+        assert_eq!(link.version, LinkVersion::ZeroKnowledge);
+        assert!(link.passphrase);
+        assert_eq!(link.to_string(), format!("{}#{}::p", ID, key()));
+    }
+
+    #[test]
+    fn missing_key() {
+        assert_eq!(ShareLink::parse(ID, ""), Err(LinkError::MissingKey));
+        assert_eq!(
+            ShareLink::parse(&format!("{}::", ID), ""),
+            Err(LinkError::MissingKey)
+        );
+        assert_eq!(
+            ShareLink::parse(&format!("{}::::p", ID), ""),
+            Err(LinkError::MissingKey)
+        );
+        assert_eq!(ShareLink::parse(ID, "::p"), Err(LinkError::MissingKey));
+    }
+
+    #[test]
+    fn invalid_id() {
+        assert_eq!(
+            ShareLink::parse(&format!("not-a-uuid::{}", key()), ""),
+            Err(LinkError::InvalidId)
+        );
+        assert_eq!(ShareLink::parse("", &key()), Err(LinkError::InvalidId));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert_eq!(
+            ShareLink::parse(&format!("{}::not base64!", ID), ""),
+            Err(LinkError::InvalidKeyEncoding)
+        );
+        let short = crypto::encode_key(&[7; 16]);
+        assert_eq!(
+            ShareLink::parse(&format!("{}::{}", ID, short), ""),
+            Err(LinkError::InvalidKeyLength(16))
+        );
+        assert_eq!(
+            ShareLink::parse(ID, &short),
+            Err(LinkError::InvalidKeyLength(16))
+        );
+    }
+
+    #[test]
+    fn from_url() {
+        let url = format!("https://tokenshare.example.com/get/{}#{}", ID, key());
+        let (server, link) = ShareLink::from_url(&url).unwrap();
+        assert_eq!(server, "https://tokenshare.example.com");
+        assert_eq!(link.version, LinkVersion::ZeroKnowledge);
+        assert_eq!(
+            ShareLink::from_url("https://tokenshare.example.com/"),
+            Err(LinkError::NotAShareLink)
+        );
+    }
+}
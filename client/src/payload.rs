@@ -0,0 +1,466 @@
+use crate::dotenv::EnvVar;
+use crate::envelope::{EnvelopeError, Reader};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+/// Longest file name or MIME type kept with an attachment, in bytes.
+const MAX_FIELD_LEN: usize = 255;
+
+/// Most bytes the payload encoding adds to the content of a secret.
+pub const MAX_OVERHEAD: usize = 1 + 2 * (2 + MAX_FIELD_LEN);
+
+/// Longest text secret accepted, in bytes.
+pub const MAX_SECRET_LEN: usize = 64 * 1024;
+
+/// Largest file accepted, in bytes.
+pub const MAX_FILE_LEN: usize = 4 * 1024 * 1024;
+
+/// Name of a file that came without a usable one.
+pub const DEFAULT_FILE_NAME: &str = "secret.bin";
+
+const KIND_TEXT: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_CREDENTIALS: u8 = 2;
+const KIND_DOTENV: u8 = 3;
+
+/// MIME type of files the browser did not recognise.
+const DEFAULT_MIME: &str = "application/octet-stream";
+
+/// What a secret decrypts to.
+///
+/// Since envelope version 4 the plaintext is `kind | content`: text is `0 | utf8`, a
+/// file is `1 | name_len (u16) | name | mime_len (u16) | mime | data` and credentials
+/// are `2` followed by `field kind | len (u32) | utf8` entries. A dotenv bundle is `3`
+/// followed by `key_len (u16) | key | value_len (u32) | value` entries. Older envelopes
+/// hold bare text.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Payload {
+    Text(Zeroizing<String>),
+    File(Attachment),
+    /// A login: username, password, URL and notes, each optional.
+    Credentials(Vec<Field>),
+    /// Environment variables, in the order they were given.
+    Dotenv(Vec<EnvVar>),
+}
+
+/// A file shared as a secret. The name and type are encrypted along with the content.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub mime: String,
+    pub data: Zeroizing<Vec<u8>>,
+}
+
+/// What a credential field holds, which decides how it is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldKind {
+    Username,
+    Password,
+    Url,
+    Notes,
+}
+
+/// One field of a credential record.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Field {
+    pub kind: FieldKind,
+    pub value: Zeroizing<String>,
+}
+
+impl FieldKind {
+    /// In the order fields are entered and shown.
+    pub const ALL: [FieldKind; 4] = [
+        FieldKind::Username,
+        FieldKind::Password,
+        FieldKind::Url,
+        FieldKind::Notes,
+    ];
+
+    fn id(self) -> u8 {
+        match self {
+            FieldKind::Username => 1,
+            FieldKind::Password => 2,
+            FieldKind::Url => 3,
+            FieldKind::Notes => 4,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        FieldKind::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FieldKind::Username => "Username",
+            FieldKind::Password => "Password",
+            FieldKind::Url => "URL",
+            FieldKind::Notes => "Notes",
+        }
+    }
+
+    /// Whether the value is hidden until the recipient asks to see it.
+    pub fn is_masked(self) -> bool {
+        self == FieldKind::Password
+    }
+}
+
+/// Encodes everything in a file payload that comes before its content, so files can be
+/// sealed while they are read. Shortens over-long names and types, and fills in a
+/// missing type.
+pub fn file_header(name: &str, mime: &str) -> Vec<u8> {
+    let mime = if mime.is_empty() { DEFAULT_MIME } else { mime };
+    let mut out = Vec::with_capacity(MAX_OVERHEAD);
+    out.push(KIND_FILE);
+    for field in [truncate(name), truncate(mime)] {
+        out.extend_from_slice(&(field.len() as u16).to_be_bytes());
+        out.extend_from_slice(field.as_bytes());
+    }
+    out
+}
+
+impl Payload {
+    pub fn encode(&self) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(Vec::new());
+        match self {
+            Payload::Text(text) => {
+                out.reserve_exact(1 + text.len());
+                out.push(KIND_TEXT);
+                out.extend_from_slice(text.as_bytes());
+            }
+            Payload::File(attachment) => {
+                out.reserve_exact(MAX_OVERHEAD + attachment.data.len());
+                out.extend_from_slice(&file_header(&attachment.name, &attachment.mime));
+                out.extend_from_slice(&attachment.data);
+            }
+            Payload::Credentials(fields) => {
+                let len = fields
+                    .iter()
+                    .map(|field| 5 + field.value.len())
+                    .sum::<usize>();
+                out.reserve_exact(1 + len);
+                out.push(KIND_CREDENTIALS);
+                for field in fields {
+                    out.push(field.kind.id());
+                    out.extend_from_slice(&(field.value.len() as u32).to_be_bytes());
+                    out.extend_from_slice(field.value.as_bytes());
+                }
+            }
+            Payload::Dotenv(vars) => {
+                let len = vars
+                    .iter()
+                    .map(|var| 6 + var.key.len() + var.value.len())
+                    .sum::<usize>();
+                out.reserve_exact(1 + len);
+                out.push(KIND_DOTENV);
+                for var in vars {
+                    out.extend_from_slice(&(var.key.len() as u16).to_be_bytes());
+                    out.extend_from_slice(var.key.as_bytes());
+                    out.extend_from_slice(&(var.value.len() as u32).to_be_bytes());
+                    out.extend_from_slice(var.value.as_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    /// Decodes the plaintext of an envelope of the given version.
+    pub fn decode(version: u8, mut plaintext: Zeroizing<Vec<u8>>) -> Result<Self, EnvelopeError> {
+        if version < 4 {
+            return Ok(Payload::from_bytes(plaintext));
+        }
+        match plaintext.first().copied() {
+            Some(KIND_TEXT) => {
+                plaintext.drain(..1);
+                Ok(Payload::from_bytes(plaintext))
+            }
+            Some(KIND_FILE) => {
+                let mut reader = Reader(&plaintext[1..]);
+                let mut field = || -> Result<String, EnvelopeError> {
+                    let len = reader.u16()? as usize;
+                    Ok(String::from_utf8_lossy(reader.take(len)?).into_owned())
+                };
+                let name = field()?;
+                let mime = field()?;
+                // The content is moved out of the plaintext rather than copied.
+                let header_len = plaintext.len() - reader.0.len();
+                plaintext.drain(..header_len);
+                let data = Zeroizing::new(std::mem::take(&mut *plaintext));
+                Ok(Payload::File(Attachment { name, mime, data }))
+            }
+            Some(KIND_CREDENTIALS) => {
+                let mut reader = Reader(&plaintext[1..]);
+                let mut fields = Vec::new();
+                while !reader.0.is_empty() {
+                    let kind = reader.u8()?;
+                    let len = reader.u32()? as usize;
+                    let value = reader.take(len)?;
+                    // Fields added by newer builds are skipped.
+                    let Some(kind) = FieldKind::from_id(kind) else {
+                        continue;
+                    };
+                    fields.push(Field {
+                        kind,
+                        value: Zeroizing::new(String::from_utf8_lossy(value).into_owned()),
+                    });
+                }
+                Ok(Payload::Credentials(fields))
+            }
+            Some(KIND_DOTENV) => {
+                let mut reader = Reader(&plaintext[1..]);
+                let mut vars = Vec::new();
+                while !reader.0.is_empty() {
+                    let len = reader.u16()? as usize;
+                    let key = String::from_utf8_lossy(reader.take(len)?).into_owned();
+                    let len = reader.u32()? as usize;
+                    let value = String::from_utf8_lossy(reader.take(len)?).into_owned();
+                    vars.push(EnvVar {
+                        key,
+                        value: Zeroizing::new(value),
+                    });
+                }
+                Ok(Payload::Dotenv(vars))
+            }
+            Some(kind) => Err(EnvelopeError::UnknownPayload(kind)),
+            None => Err(EnvelopeError::Truncated),
+        }
+    }
+
+    /// Takes bytes as text without copying them, so only one buffer needs wiping. Bytes
+    /// that are not UTF-8 are offered as a file instead.
+    pub fn from_bytes(mut bytes: Zeroizing<Vec<u8>>) -> Self {
+        match String::from_utf8(std::mem::take(&mut *bytes)) {
+            Ok(text) => Payload::Text(Zeroizing::new(text)),
+            Err(e) => Payload::File(Attachment {
+                name: DEFAULT_FILE_NAME.to_string(),
+                mime: DEFAULT_MIME.to_string(),
+                data: Zeroizing::new(e.into_bytes()),
+            }),
+        }
+    }
+}
+
+/// Cuts `field` down to `MAX_FIELD_LEN` bytes, on a character boundary.
+fn truncate(field: &str) -> &str {
+    let mut end = field.len().min(MAX_FIELD_LEN);
+    while !field.is_char_boundary(end) {
+        end -= 1;
+    }
+    &field[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::VERSION;
+
+    fn round_trip(payload: &Payload) -> Payload {
+        Payload::decode(VERSION, payload.encode()).unwrap()
+    }
+
+    fn decode(plaintext: &[u8]) -> Result<Payload, EnvelopeError> {
+        Payload::decode(VERSION, Zeroizing::new(plaintext.to_vec()))
+    }
+
+    #[test]
+    fn text_round_trips() {
+        for text in ["", "hunter2", "ünïcödé\n"] {
+            let payload = Payload::Text(Zeroizing::new(text.to_string()));
+            let Payload::Text(decoded) = round_trip(&payload) else {
+                panic!("expected text");
+            };
+            assert_eq!(*decoded, text);
+        }
+    }
+
+    #[test]
+    fn file_round_trips() {
+        let payload = Payload::File(Attachment {
+            name: "id_ed25519".to_string(),
+            mime: "text/plain".to_string(),
+            data: Zeroizing::new(vec![0, 159, 146, 150, 255]),
+        });
+        let Payload::File(decoded) = round_trip(&payload) else {
+            panic!("expected a file");
+        };
+        assert_eq!(decoded.name, "id_ed25519");
+        assert_eq!(decoded.mime, "text/plain");
+        assert_eq!(*decoded.data, [0, 159, 146, 150, 255]);
+    }
+
+    #[test]
+    fn file_header_fills_in_and_shortens() {
+        let header = file_header("a", "");
+        let Ok(Payload::File(decoded)) = decode(&header) else {
+            panic!("expected a file");
+        };
+        assert_eq!(decoded.mime, DEFAULT_MIME);
+        assert!(decoded.data.is_empty());
+
+        // 'é' is two bytes, so the cut falls inside one and has to step back.
+        let name = "é".repeat(MAX_FIELD_LEN);
+        let mime = "m".repeat(MAX_FIELD_LEN + 1);
+        let header = file_header(&name, &mime);
+        assert!(header.len() <= MAX_OVERHEAD);
+        let Ok(Payload::File(decoded)) = decode(&header) else {
+            panic!("expected a file");
+        };
+        assert_eq!(decoded.name.len(), MAX_FIELD_LEN - 1);
+        assert!(name.starts_with(&decoded.name));
+        assert_eq!(decoded.mime.len(), MAX_FIELD_LEN);
+    }
+
+    #[test]
+    fn truncated_file_headers_are_rejected() {
+        let header = file_header("name.txt", "text/plain");
+        for len in 1..header.len() {
+            assert!(
+                matches!(decode(&header[..len]), Err(EnvelopeError::Truncated)),
+                "{} bytes",
+                len
+            );
+        }
+        // A length running past the end is as good as a cut-off header.
+        let mut header = vec![KIND_FILE];
+        header.extend_from_slice(&u16::MAX.to_be_bytes());
+        header.extend_from_slice(b"short");
+        assert!(matches!(decode(&header), Err(EnvelopeError::Truncated)));
+    }
+
+    fn field(kind: FieldKind, value: &str) -> Field {
+        Field {
+            kind,
+            value: Zeroizing::new(value.to_string()),
+        }
+    }
+
+    fn fields(payload: Payload) -> Vec<(FieldKind, String)> {
+        let Payload::Credentials(fields) = payload else {
+            panic!("expected credentials");
+        };
+        fields
+            .into_iter()
+            .map(|field| (field.kind, field.value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn credentials_round_trip() {
+        let payload = Payload::Credentials(
+            FieldKind::ALL
+                .into_iter()
+                .map(|kind| field(kind, kind.label()))
+                .collect(),
+        );
+        assert_eq!(
+            fields(round_trip(&payload)),
+            FieldKind::ALL
+                .into_iter()
+                .map(|kind| (kind, kind.label().to_string()))
+                .collect::<Vec<_>>()
+        );
+        assert!(fields(round_trip(&Payload::Credentials(Vec::new()))).is_empty());
+    }
+
+    #[test]
+    fn unknown_credential_fields_are_skipped() {
+        let mut plaintext = Payload::Credentials(vec![
+            field(FieldKind::Username, "alice"),
+            field(FieldKind::Password, "hunter2"),
+        ])
+        .encode();
+        plaintext.splice(1..1, [99, 0, 0, 0, 3, b'o', b't', b'p']);
+        assert_eq!(
+            fields(decode(&plaintext).unwrap()),
+            [
+                (FieldKind::Username, "alice".to_string()),
+                (FieldKind::Password, "hunter2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_credentials_are_rejected() {
+        let plaintext = Payload::Credentials(vec![field(FieldKind::Password, "hunter2")]).encode();
+        for len in 2..plaintext.len() {
+            assert!(
+                matches!(decode(&plaintext[..len]), Err(EnvelopeError::Truncated)),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    fn dotenv(vars: &[(&str, &str)]) -> Payload {
+        Payload::Dotenv(
+            vars.iter()
+                .map(|(key, value)| EnvVar {
+                    key: key.to_string(),
+                    value: Zeroizing::new(value.to_string()),
+                })
+                .collect(),
+        )
+    }
+
+    fn vars(payload: Payload) -> Vec<(String, String)> {
+        let Payload::Dotenv(vars) = payload else {
+            panic!("expected a dotenv bundle");
+        };
+        vars.into_iter()
+            .map(|var| (var.key, var.value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dotenv_round_trips() {
+        let vars_in = [
+            ("DATABASE_URL", "postgres://localhost/db"),
+            ("EMPTY", ""),
+            ("PEM", "-----BEGIN KEY-----\nabc\n-----END KEY-----"),
+        ];
+        assert_eq!(
+            vars(round_trip(&dotenv(&vars_in))),
+            vars_in
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        );
+        assert!(vars(round_trip(&dotenv(&[]))).is_empty());
+    }
+
+    #[test]
+    fn truncated_dotenv_is_rejected() {
+        let plaintext = dotenv(&[("KEY", "value")]).encode();
+        for len in 2..plaintext.len() {
+            assert!(
+                matches!(decode(&plaintext[..len]), Err(EnvelopeError::Truncated)),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_and_empty_payloads_are_rejected() {
+        assert!(matches!(
+            decode(&[9, 1, 2]),
+            Err(EnvelopeError::UnknownPayload(9))
+        ));
+        assert!(matches!(decode(&[]), Err(EnvelopeError::Truncated)));
+    }
+
+    #[test]
+    fn older_versions_hold_bare_text() {
+        // What would be a kind byte in version 4 is part of the text.
+        let Ok(Payload::Text(text)) = Payload::decode(3, Zeroizing::new(b"\x01hi".to_vec())) else {
+            panic!("expected text");
+        };
+        assert_eq!(*text, "\u{1}hi");
+
+        let Ok(Payload::File(file)) = Payload::decode(3, Zeroizing::new(vec![0xff, 0xfe])) else {
+            panic!("expected a file");
+        };
+        assert_eq!(file.name, DEFAULT_FILE_NAME);
+        assert_eq!(file.mime, DEFAULT_MIME);
+        assert_eq!(*file.data, [0xff, 0xfe]);
+    }
+}
@@ -0,0 +1,236 @@
+//! Versioned REST API under `/api/v1/secrets`, with JSON bodies defined in
+//! `tokenshare_client::api`.
+//!
+//! Unlike the Leptos server functions, whose wire format belongs to Leptos, this is a
+//! stable interface for scripts and other clients:
+//!
+//! | Request | Success |
+//! | --- | --- |
+//! | `POST /api/v1/secrets` with a [`CreateSecret`] body | `201` with a [`SavedSecret`] |
+//! | `POST /api/v1/secrets/encrypted` with an [`EncryptedSecret`] body | `201` with a [`StoredSecret`] |
+//! | `GET /api/v1/secrets/{link}`, passphrase in `X-Passphrase` | `200` with a [`RevealedSecret`] |
+//! | `GET /api/v1/secrets/{id}/envelope` | `200` with an [`EncryptedSecret`] |
+//! | `DELETE /api/v1/secrets/{id}`, revoke token as `Authorization: Bearer` | `204` |
+//! | `GET /api/v1/secrets/{id}/status`, revoke token as `Authorization: Bearer` | `200` with a [`StatusReport`] |
+//!
+//! Failures return the status from [`SecretError::http_status`] (or `405` for a method
+//! the resource does not support) and an [`ErrorBody`].
+//!
+//! Zero-knowledge secrets are encrypted and decrypted by the client, so for them the API
+//! only stores and hands out envelopes; fetching an envelope counts as a view.
+//!
+//! [`SavedSecret`]: tokenshare_client::api::SavedSecret
+//! [`StatusReport`]: tokenshare_client::api::StatusReport
+
+use crate::error::SecretError;
+use crate::payload::Payload;
+use futures::{SinkExt, TryStreamExt};
+use serde::Serialize;
+use spin_sdk::http::{Fields, IncomingRequest, Method, OutgoingResponse, ResponseOutparam};
+use tokenshare_client::api::{
+    CreateSecret, EncryptedSecret, ErrorBody, ErrorDetail, RevealedSecret, SecretFormat,
+    StoredSecret,
+};
+use zeroize::Zeroizing;
+
+/// Where the API is mounted.
+pub const PREFIX: &str = "/api/v1/";
+
+const SECRETS_PATH: &str = "/api/v1/secrets";
+
+/// Largest request body read, in bytes: a secret of the largest size, escaped as JSON.
+const MAX_BODY_LEN: usize = 512 * 1024;
+
+/// Largest body for an encrypted secret: a base64 envelope of the largest file.
+const MAX_ENCRYPTED_BODY_LEN: usize = 8 * 1024 * 1024;
+
+/// Answered instead of the page when the path starts with [`PREFIX`].
+pub async fn handle(req: IncomingRequest, resp_out: ResponseOutparam) {
+    let (status, body) = respond(req).await.unwrap_or_else(|error| {
+        (
+            error.http_status(),
+            error_body(error.code(), error.to_string()),
+        )
+    });
+    let headers = match body {
+        Some(_) => vec![("content-type".to_string(), b"application/json".to_vec())],
+        None => vec![],
+    };
+    send(resp_out, status, headers, body).await;
+}
+
+/// Sends a response. Bodies can hold a secret, so Spin gets a copy and the original is
+/// wiped once sent. The copy is freed by the sink without being wiped.
+pub(crate) async fn send(
+    resp_out: ResponseOutparam,
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Option<Zeroizing<Vec<u8>>>,
+) {
+    let response = OutgoingResponse::new(status, &Fields::new(&headers));
+    let mut response_body = response.take_body();
+    resp_out.set(response);
+    if let Some(body) = body {
+        if let Err(e) = response_body.send(body.to_vec()).await {
+            eprintln!("Failed to send response: {:?}", e);
+        }
+    }
+}
+
+type Response = (u16, Option<Zeroizing<Vec<u8>>>);
+
+async fn respond(req: IncomingRequest) -> Result<Response, SecretError> {
+    let path = req.path_with_query().unwrap_or_default();
+    let path = path.split_once('?').map_or(path.as_str(), |(path, _)| path);
+    let resource = match path.strip_prefix(SECRETS_PATH) {
+        Some("" | "/") => None,
+        Some(rest) if rest.starts_with('/') => Some(rest[1..].trim_end_matches('/')),
+        _ => return Err(SecretError::NotFound),
+    };
+    match (req.method(), resource) {
+        (Method::Post, None) => create(req).await,
+        (Method::Post, Some("encrypted")) => create_encrypted(req).await,
+        (Method::Get, Some(resource)) => {
+            if let Some(id) = resource.strip_suffix("/status") {
+                status(&req, id).await
+            } else if let Some(id) = resource.strip_suffix("/envelope") {
+                envelope(id).await
+            } else {
+                reveal(&req, resource).await
+            }
+        }
+        (Method::Delete, Some(id)) => revoke(&req, id).await,
+        _ => Ok((
+            405,
+            error_body("method_not_allowed", "Method not allowed".to_string()),
+        )),
+    }
+}
+
+async fn create(req: IncomingRequest) -> Result<Response, SecretError> {
+    let request: CreateSecret = read_json(req, MAX_BODY_LEN).await?;
+    let saved = crate::app::savesecret::save_secret(
+        request.secret,
+        request.passphrase.unwrap_or_default(),
+        request.max_views,
+        request.ttl,
+        request.format == SecretFormat::Dotenv,
+    )
+    .await?;
+    Ok((201, Some(to_json(&saved)?)))
+}
+
+async fn create_encrypted(req: IncomingRequest) -> Result<Response, SecretError> {
+    let request: EncryptedSecret = read_json(req, MAX_ENCRYPTED_BODY_LEN).await?;
+    let revoke_token =
+        crate::app::savesecret::save_encrypted_secret(request.id.clone(), request.envelope)
+            .await?;
+    let stored = StoredSecret {
+        id: request.id,
+        revoke_token,
+    };
+    Ok((201, Some(to_json(&stored)?)))
+}
+
+/// `link` is the classic share link, as it appears after `/get/`.
+async fn reveal(req: &IncomingRequest, link: &str) -> Result<Response, SecretError> {
+    let passphrase = Zeroizing::new(header(req, "x-passphrase").unwrap_or_default());
+    let link = Zeroizing::new(link.to_string());
+    let payload = crate::app::getsecret::get_secret(link, passphrase).await?;
+    Ok((200, Some(to_json(&revealed(payload)?)?)))
+}
+
+/// The JSON form of a classically revealed secret.
+pub(crate) fn revealed(payload: Payload) -> Result<RevealedSecret, SecretError> {
+    RevealedSecret::from_payload(payload)
+        .ok_or_else(|| SecretError::Unexpected("Unsupported secret".into()))
+}
+
+async fn envelope(id: &str) -> Result<Response, SecretError> {
+    let envelope = crate::app::getsecret::get_encrypted_secret(id.to_string()).await?;
+    let encrypted = EncryptedSecret {
+        id: id.to_string(),
+        envelope,
+    };
+    Ok((200, Some(to_json(&encrypted)?)))
+}
+
+async fn revoke(req: &IncomingRequest, id: &str) -> Result<Response, SecretError> {
+    crate::app::getsecret::revoke_secret(id.to_string(), bearer_token(req)?).await?;
+    Ok((204, None))
+}
+
+async fn status(req: &IncomingRequest, id: &str) -> Result<Response, SecretError> {
+    let report =
+        crate::app::secretstatus::get_secret_status(id.to_string(), bearer_token(req)?).await?;
+    Ok((200, Some(to_json(&report)?)))
+}
+
+/// Reads a JSON request body of at most `max_len` bytes, wiped once parsed.
+async fn read_json<T: serde::de::DeserializeOwned>(
+    req: IncomingRequest,
+    max_len: usize,
+) -> Result<T, SecretError> {
+    let body = read_body(req, max_len).await?;
+    serde_json::from_slice(&body)
+        .map_err(|e| SecretError::InvalidRequest(format!("Invalid JSON: {}", e)))
+}
+
+/// Reads a request body as it streams in, failing as soon as it grows past `max_len`
+/// bytes (or declares that it will), so an oversized body is never read in full.
+pub(crate) async fn read_body(
+    req: IncomingRequest,
+    max_len: usize,
+) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+    let declared_len = header(&req, "content-length").and_then(|len| len.parse::<usize>().ok());
+    if declared_len.is_some_and(|len| len > max_len) {
+        return Err(SecretError::TooLarge);
+    }
+    // Sized up front where possible, since growing the buffer leaves copies behind.
+    let mut body = Zeroizing::new(Vec::with_capacity(declared_len.unwrap_or(0)));
+    let mut stream = std::pin::pin!(req.into_body_stream());
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .map_err(|e| SecretError::InvalidRequest(format!("Unreadable body: {:?}", e)))?
+    {
+        let chunk = Zeroizing::new(chunk);
+        if body.len() + chunk.len() > max_len {
+            return Err(SecretError::TooLarge);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// The first value of the header `name`, if it is UTF-8.
+pub(crate) fn header(req: &IncomingRequest, name: &str) -> Option<String> {
+    req.headers()
+        .entries()
+        .into_iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| String::from_utf8(value).ok())
+}
+
+fn bearer_token(req: &IncomingRequest) -> Result<String, SecretError> {
+    header(req, "authorization")
+        .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string))
+        .ok_or_else(|| SecretError::InvalidRequest("Missing revoke token".into()))
+}
+
+/// Serialises a response body for [`send`].
+pub(crate) fn to_json(value: &impl Serialize) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+    serde_json::to_vec(value)
+        .map(Zeroizing::new)
+        .map_err(|e| SecretError::Unexpected(format!("Failed to encode response: {}", e)))
+}
+
+pub(crate) fn error_body(code: &'static str, message: String) -> Option<Zeroizing<Vec<u8>>> {
+    to_json(&ErrorBody {
+        error: ErrorDetail {
+            code: code.to_string(),
+            message,
+        },
+    })
+    .ok()
+}
@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 
 pub(crate) mod getsecret;
 pub(crate) mod savesecret;
+pub(crate) mod secretstatus;
 
 #[component]
 pub fn App() -> impl IntoView {
@@ -29,6 +30,7 @@ pub fn App() -> impl IntoView {
                 <Routes>
                     <Route path="" view=savesecret::SaveSecret/>
                     <Route path="/get/:id" view=getsecret::GetSecret/>
+                    <Route path="/status/:id" view=secretstatus::SecretStatus/>
                     <Route path="/*any" view=NotFound/>
                 </Routes>
             </main>
@@ -1,18 +1,25 @@
-use base64::{engine::general_purpose, Engine as _};
-use chacha20poly1305::{
-    aead::{Aead, KeyInit},
-    ChaCha20Poly1305,
-};
+use crate::crypto;
+use crate::dotenv::{self, EnvVar};
+use crate::envelope::{unix_now, Envelope, Kdf};
+#[cfg(feature = "ssr")]
+use crate::envelope::LegacyRecord;
+use crate::error::SecretError;
+use crate::link::{LinkVersion, ShareLink};
+use crate::payload::{Attachment, Field, Payload};
 use leptos::*;
 use leptos_router::*;
-use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
+/// Longest TTL a sender may choose; also how long "already viewed" markers are kept.
+pub(crate) const MAX_TTL: u64 = 30 * 24 * 60 * 60;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct NoncedSecret {
-    nonce: Vec<u8>,
-    secret: Vec<u8>,
-}
+/// Wrong passphrases accepted for a server-decrypted secret before it is locked.
+#[cfg(feature = "ssr")]
+const MAX_PASSPHRASE_ATTEMPTS: u32 = 5;
+
+/// How long a secret stays locked after too many wrong passphrases, in seconds.
+#[cfg(feature = "ssr")]
+const PASSPHRASE_LOCKOUT: u64 = 15 * 60;
 
 // Reveal token from URL
 #[component]
@@ -20,12 +27,99 @@ pub fn GetSecret() -> impl IntoView {
     let params = use_params_map();
     let id = move || params.with(|params| params.get("id").cloned().unwrap_or_default());
     let (secret, set_secret) = create_signal("".to_string());
-    spawn_local(async move {
-        let secret_text = get_secret(id())
-            .await
-            .unwrap_or_else(|_| "Not found, sorry.".to_string());
-        set_secret.update(|text| *text = format!("{}", secret_text));
+    let (passphrase, set_passphrase) = create_signal("".to_string());
+    // A revealed file, handed to the browser when the download button is clicked.
+    let (download, set_download) = create_signal(None::<Attachment>);
+    let (credentials, set_credentials) = create_signal(Vec::<Field>::new());
+    let (dotenv, set_dotenv) = create_signal(Vec::<EnvVar>::new());
+    // Parsed in the browser: zero-knowledge links keep the key in the URL fragment,
+    // which the server never sees.
+    let (link, set_link) = create_signal(None::<ShareLink>);
+    // Nothing is fetched until the visitor asks for it: link previews and scanners that
+    // load the page must not consume a view.
+    let (pending, set_pending) = create_signal(false);
+    let (busy, set_busy) = create_signal(false);
+    let needs_passphrase =
+        move || link.with(|link| link.as_ref().map_or(false, |link| link.passphrase));
+    create_effect(move |_| match ShareLink::parse(&id(), &fragment()) {
+        Ok(parsed) => {
+            set_link.set(Some(parsed));
+            set_pending.set(true);
+        }
+        Err(e) => set_secret.set(e.to_string()),
     });
+    // A zero-knowledge secret is fetched (and its view counted) only once; a mistyped
+    // passphrase is retried against this copy.
+    let fetched = store_value(None::<Envelope>);
+    let reveal = move || {
+        let Some(link) = link.get_untracked() else {
+            return;
+        };
+        if busy.get_untracked() {
+            return;
+        }
+        set_busy.set(true);
+        let passphrase = passphrase.get_untracked();
+        spawn_local(async move {
+            let needs_passphrase = link.passphrase;
+            let result = match link.version {
+                LinkVersion::Classic => {
+                    get_secret(Zeroizing::new(id()), Zeroizing::new(passphrase))
+                        .await
+                        .map_err(SecretError::from)
+                }
+                LinkVersion::ZeroKnowledge => reveal_in_browser(link, passphrase, fetched).await,
+            };
+            let secret_text = match result {
+                Ok(Payload::Text(secret)) => {
+                    set_pending.set(false);
+                    secret.to_string()
+                }
+                Ok(Payload::Credentials(fields)) => {
+                    set_pending.set(false);
+                    set_credentials.set(fields);
+                    "".to_string()
+                }
+                Ok(Payload::Dotenv(vars)) => {
+                    set_pending.set(false);
+                    let description = format!("{} variables", vars.len());
+                    set_dotenv.set(vars);
+                    description
+                }
+                Ok(Payload::File(attachment)) => {
+                    set_pending.set(false);
+                    let description =
+                        format!("{} ({} bytes)", attachment.name, attachment.data.len());
+                    set_download.set(Some(attachment));
+                    description
+                }
+                // Worth another try, so the form stays up.
+                Err(
+                    e @ (SecretError::WrongPassphrase
+                    | SecretError::RateLimited
+                    | SecretError::StorageUnavailable
+                    | SecretError::Unexpected(_)),
+                ) => e.to_string(),
+                // In hardened mode a wrong passphrase looks like any other failure.
+                Err(e @ SecretError::Unavailable) if needs_passphrase => e.to_string(),
+                Err(e) => {
+                    set_pending.set(false);
+                    e.to_string()
+                }
+            };
+            set_secret.update(|text| *text = format!("{}", secret_text));
+            set_busy.set(false);
+        });
+    };
+    let save_file = move |_: ev::MouseEvent| {
+        let saved = download.with(|attachment| match attachment {
+            Some(file) => save_download(&file.name, &file.data, &file.mime),
+            None => Ok(()),
+        });
+        if let Err(e) = saved {
+            set_secret.set(e.to_string());
+        }
+    };
 
     view! {
         <section>
@@ -55,6 +149,46 @@ pub fn GetSecret() -> impl IntoView {
                                 {id}
                             </p>
 
+                            <Show when=move || pending.get() fallback=|| ()>
+                                <form
+                                    class="mt-6 space-y-3"
+                                    on:submit=move |ev| {
+                                        ev.prevent_default();
+                                        reveal();
+                                    }
+                                >
+                                    <p class="text-sm leading-relaxed text-gray-500">
+                                        Secrets can usually be viewed only once. Revealing it may delete it from
+                                        the server for good, so only continue when you are ready to copy it.
+                                    </p>
+                                    <Show when=needs_passphrase fallback=|| ()>
+                                        <label
+                                            for="passphrase"
+                                            class="block text-sm font-medium text-neutral-600"
+                                        >
+                                            This secret is protected by a passphrase
+                                        </label>
+                                        <input
+                                            id="passphrase"
+                                            type="password"
+                                            prop:value=passphrase
+                                            on:input=move |ev| {
+                                                set_passphrase.set(event_target_value(&ev));
+                                            }
+
+                                            class="block w-full px-5 py-3 text-base border border-transparent rounded-lg text-neutral-600 bg-gray-50 focus:outline-none focus:ring-2 focus:ring-white focus:ring-offset-2 focus:ring-offset-gray-300"
+                                        />
+                                    </Show>
+                                    <button
+                                        type="submit"
+                                        disabled=busy
+                                        class="flex items-center justify-center w-full px-10 py-3 text-base font-medium text-center text-white bg-blue-600 rounded-xl hover:bg-blue-700"
+                                    >
+                                        Reveal secret
+                                    </button>
+                                </form>
+                            </Show>
+
                             <div class="justify-end mt-6">
                                 <label for="secret" class="sr-only">
                                     Secret
@@ -86,6 +220,32 @@ pub fn GetSecret() -> impl IntoView {
                                     </div>
                                 </div>
                             </div>
+                            <Show
+                                when=move || credentials.with(|fields| !fields.is_empty())
+                                fallback=|| ()
+                            >
+                                <dl class="mt-6 space-y-3 text-left">
+                                    {move || {
+                                        credentials
+                                            .get()
+                                            .into_iter()
+                                            .map(|field| view! { <CredentialField field=field/> })
+                                            .collect_view()
+                                    }}
+                                </dl>
+                            </Show>
+                            <Show when=move || dotenv.with(|vars| !vars.is_empty()) fallback=|| ()>
+                                {move || view! { <DotenvTable vars=dotenv.get()/> }}
+                            </Show>
+                            <Show when=move || download.with(Option::is_some) fallback=|| ()>
+                                <button
+                                    type="button"
+                                    on:click=save_file
+                                    class="flex items-center justify-center w-full px-10 py-3 mt-6 text-base font-medium text-center text-white bg-blue-600 rounded-xl hover:bg-blue-700"
+                                >
+                                    Download file
+                                </button>
+                            </Show>
                         </div>
                     </div>
                 </div>
@@ -94,35 +254,738 @@ pub fn GetSecret() -> impl IntoView {
     }
 }
 
+/// A revealed credential field with a copy button. Passwords stay masked until shown.
+#[component]
+fn CredentialField(field: Field) -> impl IntoView {
+    let kind = field.kind;
+    let value = store_value(field.value);
+    let (shown, set_shown) = create_signal(!kind.is_masked());
+    let text = move || {
+        if shown.get() {
+            value.with_value(|value| value.to_string())
+        } else {
+            "••••••••".to_string()
+        }
+    };
+    view! {
+        <div class="p-3 rounded-lg bg-gray-50">
+            <dt class="text-sm font-medium text-neutral-600">{kind.label()}</dt>
+            <dd class="flex items-start justify-between gap-3 mt-1">
+                <span class="text-base text-neutral-600 break-all whitespace-pre-wrap">{text}</span>
+                <span class="flex gap-3 shrink-0">
+                    {kind
+                        .is_masked()
+                        .then(|| {
+                            view! {
+                                <button
+                                    type="button"
+                                    on:click=move |_| set_shown.update(|shown| *shown = !*shown)
+                                    class="text-sm text-blue-600 hover:text-blue-700"
+                                >
+                                    {move || if shown.get() { "Hide" } else { "Show" }}
+                                </button>
+                            }
+                        })}
+                    <button
+                        type="button"
+                        on:click=move |_| value.with_value(|value| copy_to_clipboard(value))
+                        class="text-sm text-blue-600 hover:text-blue-700"
+                    >
+                        Copy
+                    </button>
+                </span>
+            </dd>
+        </div>
+    }
+}
+
+/// Name of the Kubernetes `Secret` a revealed dotenv bundle is exported as.
+const KUBERNETES_SECRET_NAME: &str = "tokenshare";
+
+/// A revealed dotenv bundle as a table of variables with copy buttons, and downloads of
+/// the whole bundle as a `.env` file, a Kubernetes `Secret` and a `docker --env-file`.
+#[component]
+fn DotenvTable(vars: Vec<EnvVar>) -> impl IntoView {
+    let exports = [
+        (".env", ".env", dotenv::to_dotenv(&vars)),
+        (
+            "Kubernetes Secret",
+            "secret.yaml",
+            dotenv::to_kubernetes_secret(&vars, KUBERNETES_SECRET_NAME),
+        ),
+        ("docker --env-file", "docker.env", dotenv::to_docker_env_file(&vars)),
+    ];
+    let (error, set_error) = create_signal(None::<String>);
+    let downloads = exports
+        .into_iter()
+        .map(|(label, name, text)| {
+            let text = store_value(text);
+            let on_click = move |_: ev::MouseEvent| {
+                let saved =
+                    text.with_value(|text| save_download(name, text.as_bytes(), "text/plain"));
+                set_error.set(saved.err().map(|e| e.to_string()));
+            };
+            view! {
+                <button
+                    type="button"
+                    on:click=on_click
+                    class="flex-1 px-4 py-2 text-sm font-medium text-center text-blue-600 border border-blue-600 rounded-xl hover:bg-blue-50"
+                >
+                    {label}
+                </button>
+            }
+        })
+        .collect_view();
+    let rows = vars
+        .into_iter()
+        .map(|var| {
+            let value = store_value(var.value);
+            view! {
+                <tr class="border-t border-gray-200">
+                    <td class="py-2 pr-3 font-mono text-sm font-medium align-top text-neutral-600 break-all">
+                        {var.key}
+                    </td>
+                    <td class="py-2 pr-3 font-mono text-sm align-top text-neutral-600 break-all whitespace-pre-wrap">
+                        {value.with_value(|value| value.to_string())}
+                    </td>
+                    <td class="py-2 text-right align-top">
+                        <button
+                            type="button"
+                            on:click=move |_| value.with_value(|value| copy_to_clipboard(value))
+                            class="text-sm text-blue-600 hover:text-blue-700"
+                        >
+                            Copy
+                        </button>
+                    </td>
+                </tr>
+            }
+        })
+        .collect_view();
+    view! {
+        <table class="w-full mt-6 text-left table-fixed">
+            <thead>
+                <tr class="text-sm text-gray-500">
+                    <th class="w-1/3 pb-2 font-medium">Name</th>
+                    <th class="pb-2 font-medium">Value</th>
+                    <th class="w-16 pb-2"></th>
+                </tr>
+            </thead>
+            <tbody>{rows}</tbody>
+        </table>
+        <p class="mt-6 text-sm text-gray-500">Download as</p>
+        <div class="flex gap-3 mt-2">{downloads}</div>
+        <p class="mt-2 text-sm text-red-600">{error}</p>
+    }
+}
+
+/// Copies `text` to the clipboard. The Clipboard API is still unstable in `web-sys`, so
+/// it is called through `js_sys`.
+fn copy_to_clipboard(text: &str) {
+    #[cfg(feature = "hydrate")]
+    {
+        use wasm_bindgen::{JsCast, JsValue};
+
+        let copy = || -> Result<(), JsValue> {
+            let navigator = js_sys::Reflect::get(&window(), &"navigator".into())?;
+            let clipboard = js_sys::Reflect::get(&navigator, &"clipboard".into())?;
+            let write_text = js_sys::Reflect::get(&clipboard, &"writeText".into())?
+                .dyn_into::<js_sys::Function>()?;
+            write_text.call1(&clipboard, &text.into())?;
+            Ok(())
+        };
+        // Browsers without the Clipboard API (or outside a secure context) cannot copy.
+        let _ = copy();
+    }
+    #[cfg(not(feature = "hydrate"))]
+    {
+        let _ = text;
+    }
+}
+
+/// The URL fragment without the leading `#`. Only meaningful in the browser.
+pub(crate) fn fragment() -> String {
+    window()
+        .location()
+        .hash()
+        .map(|hash| hash.trim_start_matches('#').to_string())
+        .unwrap_or_default()
+}
+
+/// Fetches the ciphertext of a zero-knowledge secret (unless already fetched) and
+/// decrypts it with the key from the URL fragment.
+async fn reveal_in_browser(
+    link: ShareLink,
+    passphrase: String,
+    fetched: StoredValue<Option<Envelope>>,
+) -> Result<Payload, SecretError> {
+    let envelope = match fetched.get_value() {
+        Some(envelope) => envelope,
+        None => {
+            let envelope = Envelope::from_base64(&get_encrypted_secret(link.id.clone()).await?)
+                .map_err(|e| SecretError::Unexpected(format!("Invalid envelope: {}", e)))?;
+            fetched.set_value(Some(envelope.clone()));
+            envelope
+        }
+    };
+    let value = open_secret(&link, &envelope, &passphrase)?;
+    decode_payload(&envelope, value)
+}
+
+/// Decrypts a secret. A failure is blamed on the passphrase if the envelope needs one,
+/// and on the key otherwise.
+fn open_secret(
+    link: &ShareLink,
+    envelope: &Envelope,
+    passphrase: &str,
+) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+    crypto::open(&link.id, &link.key, envelope, passphrase).map_err(|_| open_failed(envelope))
+}
+
+/// Why `envelope` did not open: the passphrase if it needs one, the key otherwise.
+fn open_failed(envelope: &Envelope) -> SecretError {
+    match envelope.kdf {
+        Kdf::Argon2id { .. } => SecretError::WrongPassphrase,
+        Kdf::None => wrong_key(),
+    }
+}
+
+fn wrong_key() -> SecretError {
+    SecretError::InvalidLink("The key in this link does not open the secret.".into())
+}
+
+/// Decodes the plaintext of `envelope` into its payload.
+fn decode_payload(envelope: &Envelope, value: Zeroizing<Vec<u8>>) -> Result<Payload, SecretError> {
+    Payload::decode(envelope.version, value)
+        .map_err(|e| SecretError::Unexpected(format!("Corrupt secret: {}", e)))
+}
+
+/// Has the browser download revealed content as a file called `name`. The content goes
+/// through an object URL, which is revoked once the download has started so that it
+/// does not outlive the click. The browser keeps its own copy of the content, which
+/// cannot be wiped from here.
+fn save_download(name: &str, data: &[u8], mime: &str) -> Result<(), SecretError> {
+    #[cfg(feature = "hydrate")]
+    {
+        use wasm_bindgen::{JsCast, JsValue};
+
+        let save = || -> Result<(), JsValue> {
+            let data = js_sys::Uint8Array::from(data);
+            let mut options = web_sys::BlobPropertyBag::new();
+            options.type_(mime);
+            let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+                &js_sys::Array::of1(&data),
+                &options,
+            )?;
+            let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+            let link = document()
+                .create_element("a")?
+                .unchecked_into::<web_sys::HtmlAnchorElement>();
+            link.set_href(&url);
+            link.set_download(name);
+            link.click();
+            set_timeout(
+                move || {
+                    let _ = web_sys::Url::revoke_object_url(&url);
+                },
+                std::time::Duration::from_secs(1),
+            );
+            Ok(())
+        };
+        save().map_err(|_| SecretError::Unexpected("Could not prepare the download".into()))
+    }
+    #[cfg(not(feature = "hydrate"))]
+    {
+        let _ = (name, data, mime);
+        Err(SecretError::Unexpected(
+            "Files can only be downloaded in the browser".into(),
+        ))
+    }
+}
+
+/// Reveals a secret the server encrypted: text or a dotenv bundle. `id` is the whole
+/// share link, key included, so it is wiped along with the passphrase and the plaintext.
 #[server(GetSecret, "/api")]
-pub async fn get_secret(id: String) -> Result<String, ServerFnError> {
-    let v: Vec<&str> = id.split("::").collect();
-    let store = spin_sdk::key_value::Store::open_default()
-        .map_err(|e| ServerFnError::ServerError(format!("Failed to open store: {}", e)))?;
+pub async fn get_secret(
+    id: Zeroizing<String>,
+    passphrase: Zeroizing<String>,
+) -> Result<Payload, ServerFnError> {
+    let hardened = hardened();
+    reveal_on_server(&id, &passphrase, hardened).map_err(|e| conceal(e, hardened).into())
+}
+
+/// Returns the stored envelope of a zero-knowledge secret, base64-encoded; decryption
+/// happens in the browser. The server cannot tell whether decryption succeeds, so
+/// a view is counted as soon as the envelope is handed out.
+#[server(GetEncryptedSecret, "/api")]
+pub async fn get_encrypted_secret(id: String) -> Result<String, ServerFnError> {
+    let hardened = hardened();
+    let reveal = || -> Result<String, SecretError> {
+        let store = open_store()?;
+
+        let mut envelope = load_secret(&store, &id, !hardened)?;
+        // Classic secrets only open through `reveal_on_server`, which limits passphrase
+        // guesses. Their envelopes are not handed out, nor their views used up, and they
+        // get the answer a missing secret gets.
+        if envelope.is_server_encrypted() {
+            return Err(SecretError::NotFound);
+        }
+        read_chunks(&store, &id, &mut envelope)?;
+        count_view(&store, &id, &mut envelope)?;
+        envelope.metadata.clear_private();
+        Ok(envelope.to_base64())
+    };
+    reveal().map_err(|e| conceal(e, hardened).into())
+}
+
+/// Whether failures to reveal a secret all look the same to the client: the `hardened`
+/// Spin variable. Without it, visitors learn why a link stopped working, but a prober
+/// can tell which ids exist.
+#[cfg(feature = "ssr")]
+fn hardened() -> bool {
+    spin_sdk::variables::get("hardened").map_or(false, |value| value == "true")
+}
+
+/// In hardened mode, logs the precise error and replaces it with the one response every
+/// failure gets.
+#[cfg(feature = "ssr")]
+fn conceal(error: SecretError, hardened: bool) -> SecretError {
+    if !hardened {
+        return error;
+    }
+    eprintln!("Reveal failed: {:?}", error);
+    SecretError::Unavailable
+}
+
+#[cfg(feature = "ssr")]
+fn reveal_on_server(
+    id: &str,
+    passphrase: &str,
+    hardened: bool,
+) -> Result<Payload, SecretError> {
+    let link = ShareLink::parse(id, "").map_err(|e| SecretError::InvalidLink(e.to_string()))?;
+    let store = open_store()?;
+
+    let fail_early = |e: SecretError| {
+        if hardened {
+            equalise_work(&link, passphrase);
+        }
+        e
+    };
 
-    let nonce_secret = store
-        .get_json::<NoncedSecret>(v[0])
-        .map_err(|e| ServerFnError::ServerError(format!("Failed to get JSON from store: {}", e)))?;
+    let mut envelope = load_secret(&store, &link.id, !hardened).map_err(fail_early)?;
+    // The marker decides how much work a failure before decryption does, so a link whose
+    // marker does not fit the secret is turned away rather than decrypted at another cost.
+    if link.passphrase != matches!(envelope.kdf, Kdf::Argon2id { .. }) {
+        return Err(fail_early(wrong_key()));
+    }
+    // Checked before anything is counted, so a wrong or made-up link never locks a secret.
+    if let Some(key_check) = &envelope.metadata.key_check {
+        if *key_check != crate::tokens::key_check(&link.id, &link.key) {
+            return Err(fail_early(wrong_key()));
+        }
+    }
+    // With a checked link, only the passphrase can be wrong.
+    let guarded =
+        envelope.metadata.key_check.is_some() && matches!(envelope.kdf, Kdf::Argon2id { .. });
+    let now = unix_now();
+    if guarded
+        && envelope
+            .metadata
+            .is_locked_out(now, MAX_PASSPHRASE_ATTEMPTS, PASSPHRASE_LOCKOUT)
+    {
+        return Err(fail_early(SecretError::RateLimited));
+    }
+
+    let value = match open_stored(&store, &link, &envelope, passphrase) {
+        Ok(value) => value,
+        Err(e) => {
+            if guarded {
+                record_failed_attempt(&store, &link.id, envelope, now)?;
+            }
+            return Err(e);
+        }
+    };
+
+    let payload = match decode_payload(&envelope, value)? {
+        payload @ (Payload::Text(_) | Payload::Dotenv(_) | Payload::File(_)) => payload,
+        _ => {
+            return Err(SecretError::InvalidRequest(
+                "This secret can only be revealed in the browser".into(),
+            ))
+        }
+    };
+
+    count_view(&store, &link.id, &mut envelope)?;
+
+    Ok(payload)
+}
+
+/// Does the work of a failed decryption for hardened reveals that fail before one (the
+/// secret is missing, the link is wrong or the secret is locked), so they take as long:
+/// the same key derivation, picked by the marker in the link, and decryption. No chunk
+/// has been read by then, so large secrets do not stand out either.
+#[cfg(feature = "ssr")]
+fn equalise_work(link: &ShareLink, passphrase: &str) {
+    use crate::envelope::{CipherSuite, Metadata, VERSION};
+
+    let suite = CipherSuite::XChaCha20Poly1305Stream;
+    let decoy = Envelope {
+        version: VERSION,
+        suite,
+        kdf: if link.passphrase {
+            crypto::argon2id(vec![0; crypto::SALT_LEN])
+        } else {
+            Kdf::None
+        },
+        nonce: vec![0; suite.nonce_len()],
+        metadata: Metadata::default(),
+        ciphertext: vec![0; crypto::sealed_len(0, &crypto::DEFAULT_PADDING_BUCKETS)],
+    };
+    let _ = crypto::open(&link.id, &link.key, &decoy, passphrase);
+}
+
+/// Deletes a secret before it is used up, given the revoke token returned when it was
+/// saved. Later visits to the link say that the secret was revoked.
+#[server(RevokeSecret, "/api", "Url", "revoke_secret")]
+pub async fn revoke_secret(id: String, revoke_token: String) -> Result<(), ServerFnError> {
+    let hardened = hardened();
+    let revoke = || -> Result<(), SecretError> {
+        let store = open_store()?;
+
+        let envelope = load_secret(&store, &id, !hardened)?;
+        // A wrong token gets the answer a missing secret gets, so it does not tell which
+        // ids exist. Comparing hashes rather than tokens keeps the comparison independent
+        // of the token.
+        if envelope.metadata.revoke_hash != Some(crate::tokens::hash_revoke_token(&revoke_token))
+        {
+            return Err(SecretError::NotFound);
+        }
+        delete_secret(&store, &id, &envelope)?;
+        store
+            .set(&revoked_key(&id), unix_now().to_string().as_bytes())
+            .map_err(|e| SecretError::storage("Failed to mark secret as revoked", e))?;
+        if let Err(e) = super::secretstatus::record_revoked(&store, &id) {
+            eprintln!("Recording revocation of {} failed: {}", id, e);
+        }
+        Ok(())
+    };
+    revoke().map_err(|e| conceal(e, hardened).into())
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) fn open_store() -> Result<spin_sdk::key_value::Store, SecretError> {
+    spin_sdk::key_value::Store::open_default()
+        .map_err(|e| SecretError::storage("Failed to open store", e))
+}
+
+/// Key of the marker left behind when a one-time secret is burned, so that a second
+/// visit can say "already viewed" rather than "not found".
+#[cfg(feature = "ssr")]
+pub(crate) fn viewed_key(id: &str) -> String {
+    format!("viewed:{}", id)
+}
+
+/// Key of the marker left behind when the sender revokes a secret.
+#[cfg(feature = "ssr")]
+pub(crate) fn revoked_key(id: &str) -> String {
+    format!("revoked:{}", id)
+}
+
+/// Largest ciphertext stored in a single value. Larger ones, i.e. files, are split into
+/// chunks stored under `chunk:{id}:{n}`, to stay under the value size limit of the store.
+/// Each chunk holds whole sealed segments (see `crypto::Sealer`).
+#[cfg(feature = "ssr")]
+const CHUNK_LEN: usize = 8 * (crypto::SEGMENT_LEN + crypto::TAG_LEN);
+
+#[cfg(feature = "ssr")]
+const CHUNK_PREFIX: &str = "chunk:";
+
+#[cfg(feature = "ssr")]
+fn chunk_key(id: &str, n: u32) -> String {
+    format!("{}{}:{}", CHUNK_PREFIX, id, n)
+}
+
+/// Writes a new secret, splitting a large ciphertext into chunks. The record under `id`
+/// is then a manifest: the envelope without its ciphertext, with the number of chunks.
+/// It is written first and removed again if a chunk cannot be written, so chunks never
+/// outlive it.
+#[cfg(feature = "ssr")]
+pub(crate) fn write_envelope(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    mut envelope: Envelope,
+) -> Result<(), SecretError> {
+    if envelope.ciphertext.len() <= CHUNK_LEN {
+        envelope.metadata.chunks = None;
+        return write_record(store, id, &envelope);
+    }
+    envelope.metadata.chunks = Some(envelope.ciphertext.chunks(CHUNK_LEN).len() as u32);
+    write_record(store, id, &envelope)?;
+    for (n, chunk) in envelope.ciphertext.chunks(CHUNK_LEN).enumerate() {
+        if let Err(e) = store.set(&chunk_key(id, n as u32), chunk) {
+            let _ = delete_secret(store, id, &envelope);
+            return Err(SecretError::storage("Failed to write chunk", e));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the record under `id`, without the ciphertext if that is stored in chunks.
+/// Used on its own when only the metadata changed.
+#[cfg(feature = "ssr")]
+fn write_record(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    envelope: &Envelope,
+) -> Result<(), SecretError> {
+    let bytes = match envelope.metadata.chunks {
+        Some(_) => envelope.encode_header(),
+        None => envelope.encode(),
+    };
+    store
+        .set(id, &bytes)
+        .map_err(|e| SecretError::storage("Failed to write to store", e))
+}
 
-    let nonce_secret =
-        nonce_secret.ok_or_else(|| ServerFnError::ServerError("Secret not found".into()))?;
+/// Deletes a secret and its chunks. The record goes first, so a failure part-way leaves
+/// only chunks, which the sweep removes.
+#[cfg(feature = "ssr")]
+fn delete_secret(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    envelope: &Envelope,
+) -> Result<(), SecretError> {
+    store
+        .delete(id)
+        .map_err(|e| SecretError::storage("Failed to delete secret", e))?;
+    for n in 0..envelope.metadata.chunks.unwrap_or(0) {
+        store
+            .delete(&chunk_key(id, n))
+            .map_err(|e| SecretError::storage("Failed to delete chunk", e))?;
+    }
+    Ok(())
+}
+
+/// Joins the chunks of the secret stored under `id` onto the ciphertext of its record,
+/// for handing the whole envelope out. Kept apart from `load_secret`, so that a reveal
+/// can fail before any chunk is read.
+#[cfg(feature = "ssr")]
+fn read_chunks(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    envelope: &mut Envelope,
+) -> Result<(), SecretError> {
+    let chunks = envelope.metadata.chunks.unwrap_or(0);
+    envelope.ciphertext.reserve(chunks as usize * CHUNK_LEN);
+    for n in 0..chunks {
+        envelope
+            .ciphertext
+            .extend_from_slice(&read_chunk(store, id, n)?);
+    }
+    Ok(())
+}
 
-    let key = general_purpose::URL_SAFE
-        .decode(v[1])
-        .map_err(|e| ServerFnError::ServerError(format!("Failed to decode key: {}", e)))?;
+#[cfg(feature = "ssr")]
+fn read_chunk(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    n: u32,
+) -> Result<Vec<u8>, SecretError> {
+    store
+        .get(&chunk_key(id, n))
+        .map_err(|e| SecretError::storage("Failed to read chunk", e))?
+        .ok_or_else(|| SecretError::Unexpected(format!("Missing chunk {} of secret", n)))
+}
 
-    let cipher = ChaCha20Poly1305::new(
-        chacha20poly1305::aead::generic_array::GenericArray::from_slice(&key),
-    );
+/// Decrypts a secret loaded with `load_secret`, feeding a chunked ciphertext to the
+/// decryption one chunk at a time rather than joining it. A wrong key fails on the first
+/// chunk, before the others are read.
+#[cfg(feature = "ssr")]
+fn open_stored(
+    store: &spin_sdk::key_value::Store,
+    link: &ShareLink,
+    envelope: &Envelope,
+    passphrase: &str,
+) -> Result<Zeroizing<Vec<u8>>, SecretError> {
+    let Some(chunks) = envelope.metadata.chunks else {
+        return open_secret(link, envelope, passphrase);
+    };
+    let failed = |_| open_failed(envelope);
+    // Only the last chunk can be shorter, so this bounds the ciphertext.
+    let len = chunks as usize * CHUNK_LEN;
+    let mut opener =
+        crypto::Opener::new(&link.id, &link.key, envelope, passphrase, len).map_err(failed)?;
+    for n in 0..chunks {
+        opener
+            .write(&read_chunk(store, &link.id, n)?)
+            .map_err(failed)?;
+    }
+    opener.finish().map_err(failed)
+}
 
-    let nonce =
-        chacha20poly1305::aead::generic_array::GenericArray::from_slice(&nonce_secret.nonce);
+/// Reads the record stored under `key`, converting legacy JSON records. Chunked
+/// ciphertexts are left out.
+#[cfg(feature = "ssr")]
+fn read_record(
+    store: &spin_sdk::key_value::Store,
+    key: &str,
+) -> Result<Option<Envelope>, SecretError> {
+    let bytes = store
+        .get(key)
+        .map_err(|e| SecretError::storage("Failed to read from store", e))?;
+    let Some(bytes) = bytes else {
+        return Ok(None);
+    };
+    let envelope = if bytes.first() == Some(&b'{') {
+        serde_json::from_slice::<LegacyRecord>(&bytes)
+            .map(Envelope::from)
+            .map_err(|e| SecretError::Unexpected(format!("Corrupt legacy record: {}", e)))?
+    } else {
+        Envelope::decode(&bytes)
+            .map_err(|e| SecretError::Unexpected(format!("Corrupt record: {}", e)))?
+    };
+    Ok(Some(envelope))
+}
 
-    let ciphertext = nonce_secret.secret;
+/// Loads the record of a secret that can still be revealed, without the chunks of a
+/// chunked ciphertext (see `read_chunks` and `open_stored`). With `explain`, a missing
+/// secret is looked up further to say whether it was viewed or revoked rather than
+/// never existed.
+#[cfg(feature = "ssr")]
+fn load_secret(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    explain: bool,
+) -> Result<Envelope, SecretError> {
+    match read_record(store, id)? {
+        Some(envelope) if envelope.metadata.is_expired(unix_now()) => {
+            delete_secret(store, id, &envelope)?;
+            Err(SecretError::Expired)
+        }
+        Some(envelope) => Ok(envelope),
+        None if !explain => Err(SecretError::NotFound),
+        None if store.exists(&viewed_key(id)).unwrap_or(false) => Err(SecretError::AlreadyViewed),
+        None if store.exists(&revoked_key(id)).unwrap_or(false) => Err(SecretError::Revoked),
+        None => Err(SecretError::NotFound),
+    }
+}
 
-    let value = cipher
-        .decrypt(&nonce, ciphertext.as_ref())
-        .map_err(|e| ServerFnError::ServerError(format!("Decryption failed: {}", e)))?;
+/// Counts a wrong passphrase against the secret, so that guessing it through the server
+/// is slowed down to a few attempts per lockout period.
+#[cfg(feature = "ssr")]
+fn record_failed_attempt(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    mut envelope: Envelope,
+    now: u64,
+) -> Result<(), SecretError> {
+    let metadata = &mut envelope.metadata;
+    metadata.failed_attempts = Some(metadata.failed_attempts.unwrap_or(0).saturating_add(1));
+    metadata.last_failed_at = Some(now);
+    write_record(store, id, &envelope)
+}
+
+/// Decrements the view counter after a successful reveal, burning the secret when the
+/// last view is used up. Not atomic: the store has no compare-and-swap, so concurrent
+/// reveals can each see the same count.
+#[cfg(feature = "ssr")]
+fn count_view(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    envelope: &mut Envelope,
+) -> Result<(), SecretError> {
+    let remaining = envelope
+        .metadata
+        .remaining_views
+        .map(|remaining| remaining.saturating_sub(1));
+    match remaining {
+        None => {}
+        Some(0) => burn_secret(store, id, envelope)?,
+        Some(remaining) => {
+            envelope.metadata.remaining_views = Some(remaining);
+            write_record(store, id, envelope)?;
+        }
+    }
+    // The status page is informational; failing to update it must not fail the reveal.
+    if let Err(e) = super::secretstatus::record_view(store, id, remaining) {
+        eprintln!("Recording view of {} failed: {}", id, e);
+    }
+    Ok(())
+}
+
+/// Deletes a secret whose last view was revealed. Failing to delete fails the whole
+/// reveal, so a secret is never handed out while it stays readable.
+#[cfg(feature = "ssr")]
+fn burn_secret(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    envelope: &Envelope,
+) -> Result<(), SecretError> {
+    delete_secret(store, id, envelope)?;
+    store
+        .set(&viewed_key(id), unix_now().to_string().as_bytes())
+        .map_err(|e| SecretError::storage("Failed to mark secret as viewed", e))
+}
+
+/// Walks every key in the store and deletes expired secrets, as well as "already
+/// viewed" and "revoked" markers and status records old enough that the secret would
+/// have expired anyway, and chunks whose secret is gone. Returns the number of deleted
+/// secrets and other entries, not counting the chunks of deleted secrets.
+#[cfg(feature = "ssr")]
+pub(crate) fn sweep_store(store: &spin_sdk::key_value::Store) -> Result<usize, SecretError> {
+    let now = unix_now();
+    let keys = store
+        .get_keys()
+        .map_err(|e| SecretError::storage("Failed to list keys", e))?;
+
+    let mut purged = 0;
+    for key in keys {
+        let marker = key.starts_with("viewed:") || key.starts_with("revoked:");
+        let stale = if marker {
+            store
+                .get(&key)
+                .ok()
+                .flatten()
+                .and_then(|marked_at| String::from_utf8(marked_at).ok()?.parse::<u64>().ok())
+                .map_or(false, |marked_at| now >= marked_at.saturating_add(MAX_TTL))
+        } else if key.starts_with(super::secretstatus::STATUS_PREFIX) {
+            super::secretstatus::is_stale(store, &key, now)
+        } else if let Some(chunk) = key.strip_prefix(CHUNK_PREFIX) {
+            // The second check skips chunks deleted with their secret earlier on.
+            chunk
+                .rsplit_once(':')
+                .map_or(false, |(id, _)| !store.exists(id).unwrap_or(true))
+                && store.exists(&key).unwrap_or(false)
+        } else {
+            if let Ok(Some(envelope)) = read_record(store, &key) {
+                if envelope.metadata.is_expired(now)
+                    && delete_secret(store, &key, &envelope).is_ok()
+                {
+                    purged += 1;
+                }
+            }
+            continue;
+        };
+        if stale && store.delete(&key).is_ok() {
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Purges expired secrets from the store. Safe to call from a scheduler (or by hand);
+/// secrets are also expired lazily when they are requested. Every call reads the whole
+/// store, so it needs the `sweep_token` Spin variable, and is refused while that is unset.
+#[server(SweepExpired, "/api", "Url", "sweep_expired")]
+pub async fn sweep_expired(token: String) -> Result<usize, ServerFnError> {
+    let expected = spin_sdk::variables::get("sweep_token").unwrap_or_default();
+    // Compared as hashes, so the time taken says nothing about the expected token.
+    if expected.is_empty()
+        || crate::tokens::hash_revoke_token(&token) != crate::tokens::hash_revoke_token(&expected)
+    {
+        return Err(SecretError::InvalidRequest("Invalid sweep token".into()).into());
+    }
+    let store = open_store()?;
 
-    Ok(String::from_utf8(value).unwrap_or_else(|_| "Invalid UTF-8".to_string()))
+    Ok(sweep_store(&store)?)
 }
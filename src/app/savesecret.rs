@@ -1,33 +1,149 @@
-use base64::{engine::general_purpose, Engine as _};
-use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    ChaCha20Poly1305,
+use crate::crypto;
+use crate::dotenv;
+use crate::envelope::{unix_now, Envelope, Kdf, Metadata};
+use crate::error::SecretError;
+use crate::link::{LinkVersion, ShareLink};
+use crate::payload::{self, Field, FieldKind, Payload, MAX_FILE_LEN, MAX_SECRET_LEN};
+use leptos::{
+    ev::{Event, MouseEvent},
+    *,
 };
-use leptos::{ev::MouseEvent, *};
-use serde::{Deserialize, Serialize};
+use tokenshare_client::api::SavedSecret;
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct NoncedSecret {
-    nonce: Vec<u8>,
-    secret: Vec<u8>,
-}
+/// Lifetimes offered in the form, in seconds.
+const TTL_OPTIONS: [(u64, &str); 5] = [
+    (60 * 60, "1 hour"),
+    (24 * 60 * 60, "1 day"),
+    (7 * 24 * 60 * 60, "7 days"),
+    (14 * 24 * 60 * 60, "14 days"),
+    (super::getsecret::MAX_TTL, "30 days"),
+];
+#[cfg(feature = "ssr")]
+const MIN_TTL: u64 = 60 * 60;
+const DEFAULT_TTL: u64 = 24 * 60 * 60;
+
+/// View limits offered in the form; 0 means unlimited.
+const VIEW_OPTIONS: [(u32, &str); 5] = [
+    (1, "Once (burn after reading)"),
+    (3, "3 times"),
+    (5, "5 times"),
+    (10, "10 times"),
+    (0, "Unlimited until it expires"),
+];
+#[cfg(feature = "ssr")]
+const MAX_VIEWS: u32 = 100;
+/// How far the creation time stamped by a zero-knowledge client may be off.
+#[cfg(feature = "ssr")]
+const MAX_CLOCK_SKEW: u64 = 10 * 60;
 
 /// Main page with form to save secret
 #[component]
 pub fn SaveSecret() -> impl IntoView {
     let (token, set_token) = create_signal("".to_string());
+    let (passphrase, set_passphrase) = create_signal("".to_string());
+    // Shared instead of the text when set. Files are always encrypted in the browser.
+    let (attachment, set_attachment) = create_signal(None::<PickedFile>);
+    // Credentials mode: typed fields instead of the text, also encrypted in the browser.
+    let (credentials, set_credentials) = create_signal(false);
+    let fields = FieldKind::ALL.map(|kind| (kind, create_rw_signal("".to_string())));
+    // The text is a dotenv bundle, shown to the recipient as a table of variables.
+    let (dotenv, set_dotenv) = create_signal(false);
     let (url, set_url) = create_signal("".to_string());
+    let (zero_knowledge, set_zero_knowledge) = create_signal(true);
+    let (max_views, set_max_views) = create_signal(1);
+    let (ttl, set_ttl) = create_signal(DEFAULT_TTL);
+    let (saved, set_saved) = create_signal(None::<SavedSecret>);
+    let (revoked, set_revoked) = create_signal(false);
+    let (error, set_error) = create_signal("".to_string());
+    let on_file = move |event: Event| match pick_file(&event) {
+        Ok(file) => {
+            set_error.set("".to_string());
+            set_attachment.set(file);
+        }
+        Err(e) => {
+            set_error.set(e.to_string());
+            set_attachment.set(None);
+        }
+    };
     let on_click = move |_event: MouseEvent| {
+        let len = if credentials.get() {
+            fields
+                .iter()
+                .map(|(_, value)| value.with(String::len))
+                .sum::<usize>()
+        } else {
+            token.with(String::len)
+        };
+        if len > MAX_SECRET_LEN {
+            set_error.set(SecretError::TooLarge.to_string());
+            return;
+        }
         spawn_local(async move {
-            let secret_url = match save_secret(token.get().to_string()).await {
-                Ok(url) => url,
+            let content = match attachment.get_untracked() {
+                Some(file) => Content::File(file),
+                None if credentials.get_untracked() => {
+                    Content::Inline(Payload::Credentials(filled_fields(&fields)))
+                }
+                // Checked here too, so mistakes show before anything is sent.
+                None if dotenv.get_untracked() => match dotenv::parse(&token.get_untracked()) {
+                    Ok(vars) => Content::Inline(Payload::Dotenv(vars)),
+                    Err(e) => {
+                        set_error.set(e.to_string());
+                        return;
+                    }
+                },
+                None => Content::Inline(Payload::Text(Zeroizing::new(token.get()))),
+            };
+            let saved_secret = match content {
+                // The server parses the bundle again from the text.
+                Content::Inline(Payload::Text(_) | Payload::Dotenv(_)) if !zero_knowledge.get() => {
+                    save_secret(
+                        Zeroizing::new(token.get()),
+                        Zeroizing::new(passphrase.get()),
+                        max_views.get(),
+                        ttl.get(),
+                        dotenv.get(),
+                    )
+                    .await
+                    .map_err(SecretError::from)
+                }
+                content => {
+                    save_in_browser(content, passphrase.get(), max_views.get(), ttl.get()).await
+                }
+            };
+            let saved_secret = match saved_secret {
+                Ok(saved_secret) => saved_secret,
                 Err(e) => {
-                    println!("Error: {}", e);
+                    set_error.set(e.to_string());
                     return;
                 }
             };
-            set_url.update(|url| *url = format!("/get/{}", secret_url));
+            set_error.set("".to_string());
+            set_url.update(|url| *url = format!("/get/{}", saved_secret.link.as_str()));
+            set_revoked.set(false);
+            set_saved.set(Some(saved_secret));
+        });
+    };
+    // Private link to the status page; the revoke token stays in the URL fragment.
+    let status_url = move || {
+        saved.with(|saved| {
+            saved.as_ref().map(|saved_secret| {
+                format!("/status/{}#{}", saved_secret.id, saved_secret.revoke_token)
+            })
+        })
+    };
+    let on_revoke = move |_event: MouseEvent| {
+        let Some(saved_secret) = saved.get_untracked() else {
+            return;
+        };
+        spawn_local(async move {
+            match super::getsecret::revoke_secret(saved_secret.id, saved_secret.revoke_token).await
+            {
+                Ok(()) => set_revoked.set(true),
+                Err(e) => set_error.set(SecretError::from(e).to_string()),
+            }
         });
     };
     view! {
@@ -59,7 +175,30 @@ pub fn SaveSecret() -> impl IntoView {
                         <div class="mt-8">
                             <div class="mt-6">
                                 <div class="space-y-6">
-                                    <div>
+                                    <div class="flex items-center">
+                                        <input
+                                            id="credentials"
+                                            type="checkbox"
+                                            prop:checked=credentials
+                                            on:change=move |ev| {
+                                                set_credentials.set(event_target_checked(&ev));
+                                            }
+
+                                            class="w-4 h-4 text-blue-600 border-gray-300 rounded"
+                                        />
+                                        <label
+                                            for="credentials"
+                                            class="block ml-2 text-sm text-neutral-600"
+                                        >
+                                            Share login credentials (encrypted in my browser)
+                                        </label>
+                                    </div>
+
+                                    <div class="space-y-3" class:hidden=move || !credentials.get()>
+                                        {fields.map(field_input).collect_view()}
+                                    </div>
+
+                                    <div class:hidden=move || credentials.get()>
                                         <label
                                             for="token"
                                             class="block text-sm font-medium text-neutral-600"
@@ -76,10 +215,148 @@ pub fn SaveSecret() -> impl IntoView {
                                                     set_token.update(|token| *token = event_target_value(&ev));
                                                 }
 
-                                                placeholder="Type your secret here"
+                                                placeholder=move || {
+                                                    if dotenv.get() {
+                                                        "DATABASE_URL=postgres://...\nAPI_KEY=..."
+                                                    } else {
+                                                        "Type your secret here"
+                                                    }
+                                                }
+
                                                 class="block w-full px-5 py-3 text-base placeholder-gray-300 transition duration-500 ease-in-out transform border border-transparent rounded-lg text-neutral-600 bg-gray-50 focus:outline-none focus:border-transparent focus:ring-2 focus:ring-white focus:ring-offset-2 focus:ring-offset-gray-300"
                                             ></textarea>
                                         </div>
+                                        <div class="flex items-center mt-2">
+                                            <input
+                                                id="dotenv"
+                                                type="checkbox"
+                                                prop:checked=dotenv
+                                                on:change=move |ev| {
+                                                    set_dotenv.set(event_target_checked(&ev));
+                                                }
+
+                                                class="w-4 h-4 text-blue-600 border-gray-300 rounded"
+                                            />
+                                            <label for="dotenv" class="block ml-2 text-sm text-neutral-600">
+                                                "This is a .env file (KEY=value lines)"
+                                            </label>
+                                        </div>
+                                    </div>
+
+                                    <div>
+                                        <label
+                                            for="file"
+                                            class="block text-sm font-medium text-neutral-600"
+                                        >
+                                            Or share a file (up to 4 MiB)
+                                        </label>
+                                        <input
+                                            id="file"
+                                            type="file"
+                                            on:change=on_file
+                                            class="block w-full mt-1 text-sm text-neutral-600"
+                                        />
+                                        <p class="mt-1 text-xs text-gray-500">
+                                            Files are always encrypted in your browser and are shared instead of the text.
+                                        </p>
+                                    </div>
+
+                                    <div>
+                                        <label
+                                            for="passphrase"
+                                            class="block text-sm font-medium text-neutral-600"
+                                        >
+                                            Passphrase (optional)
+                                        </label>
+                                        <div class="mt-1">
+                                            <input
+                                                id="passphrase"
+                                                type="password"
+                                                autocomplete="new-password"
+                                                prop:value=passphrase
+                                                on:input=move |ev| {
+                                                    set_passphrase.set(event_target_value(&ev));
+                                                }
+
+                                                placeholder="Share it with the recipient separately"
+                                                class="block w-full px-5 py-3 text-base placeholder-gray-300 border border-transparent rounded-lg text-neutral-600 bg-gray-50 focus:outline-none focus:ring-2 focus:ring-white focus:ring-offset-2 focus:ring-offset-gray-300"
+                                            />
+                                        </div>
+                                    </div>
+
+                                    <div>
+                                        <label
+                                            for="ttl"
+                                            class="block text-sm font-medium text-neutral-600"
+                                        >
+                                            Expires after
+                                        </label>
+                                        <select
+                                            id="ttl"
+                                            on:change=move |ev| {
+                                                set_ttl.set(event_target_value(&ev).parse().unwrap_or(DEFAULT_TTL));
+                                            }
+
+                                            class="block w-full px-5 py-3 mt-1 text-base border border-transparent rounded-lg text-neutral-600 bg-gray-50"
+                                        >
+                                            {TTL_OPTIONS
+                                                .iter()
+                                                .map(|(seconds, label)| {
+                                                    view! {
+                                                        <option value=seconds.to_string() selected={*seconds == DEFAULT_TTL}>
+                                                            {*label}
+                                                        </option>
+                                                    }
+                                                })
+                                                .collect_view()}
+                                        </select>
+                                    </div>
+
+                                    <div>
+                                        <label
+                                            for="max-views"
+                                            class="block text-sm font-medium text-neutral-600"
+                                        >
+                                            Can be viewed
+                                        </label>
+                                        <select
+                                            id="max-views"
+                                            on:change=move |ev| {
+                                                set_max_views.set(event_target_value(&ev).parse().unwrap_or(1));
+                                            }
+
+                                            class="block w-full px-5 py-3 mt-1 text-base border border-transparent rounded-lg text-neutral-600 bg-gray-50"
+                                        >
+                                            {VIEW_OPTIONS
+                                                .iter()
+                                                .map(|(views, label)| {
+                                                    view! {
+                                                        <option value=views.to_string() selected={*views == 1}>
+                                                            {*label}
+                                                        </option>
+                                                    }
+                                                })
+                                                .collect_view()}
+                                        </select>
+                                    </div>
+
+                                    <div class="flex items-center">
+                                        <input
+                                            id="zero-knowledge"
+                                            type="checkbox"
+                                            prop:checked=zero_knowledge
+                                            on:change=move |ev| {
+                                                set_zero_knowledge.set(event_target_checked(&ev));
+                                            }
+
+                                            class="w-4 h-4 text-blue-600 border-gray-300 rounded"
+                                        />
+                                        <label
+                                            for="zero-knowledge"
+                                            class="block ml-2 text-sm text-neutral-600"
+                                        >
+                                            Encrypt in my browser (the server never sees the key)
+                                        </label>
                                     </div>
 
                                     <div>
@@ -90,6 +367,7 @@ pub fn SaveSecret() -> impl IntoView {
                                             Generate
                                         </button>
                                     </div>
+                                    <p class="text-sm text-red-600">{error}</p>
                                 </div>
                                 <div class="relative my-4">
                                     <div class="absolute inset-0 flex items-center">
@@ -106,6 +384,30 @@ pub fn SaveSecret() -> impl IntoView {
                                         {move || url.get()}
                                     </a>
                                 </div>
+                                <Show when=move || saved.with(Option::is_some) fallback=|| ()>
+                                    <p class="mt-4 text-sm text-gray-500">
+                                        "Keep this private link to see when the secret is read: "
+                                        <a href=status_url class="text-blue-600 break-all">
+                                            {status_url}
+                                        </a>
+                                    </p>
+                                </Show>
+                                <Show
+                                    when=move || saved.with(Option::is_some) && !revoked.get()
+                                    fallback=|| ()
+                                >
+                                    <button
+                                        on:click=on_revoke
+                                        class="flex items-center justify-center w-full px-10 py-3 mt-4 text-base font-medium text-center text-red-600 border border-red-600 rounded-xl hover:bg-red-50"
+                                    >
+                                        Revoke
+                                    </button>
+                                </Show>
+                                <Show when=move || revoked.get() fallback=|| ()>
+                                    <p class="mt-4 text-sm text-gray-500">
+                                        Revoked. The link no longer works.
+                                    </p>
+                                </Show>
                             </div>
                         </div>
                     </div>
@@ -208,30 +510,357 @@ pub fn SaveSecret() -> impl IntoView {
     }
 }
 
-#[server(SaveSecret, "/api")]
-pub async fn save_secret(token: String) -> Result<String, ServerFnError> {
-    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
-    let cipher = ChaCha20Poly1305::new(&key);
-    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng); // 96-bits; unique per message
+/// Input for one credential field.
+fn field_input((kind, value): (FieldKind, RwSignal<String>)) -> impl IntoView {
+    let id = format!("field-{}", kind.label().to_lowercase());
+    let class = "block w-full px-5 py-3 mt-1 text-base border border-transparent rounded-lg text-neutral-600 bg-gray-50 focus:outline-none focus:ring-2 focus:ring-white focus:ring-offset-2 focus:ring-offset-gray-300";
+    let input = match kind {
+        FieldKind::Notes => view! {
+            <textarea
+                id=id.clone()
+                prop:value=value
+                on:input=move |ev| value.set(event_target_value(&ev))
+                class=class
+            ></textarea>
+        }
+        .into_view(),
+        _ => view! {
+            <input
+                id=id.clone()
+                type=match kind {
+                    FieldKind::Password => "password",
+                    FieldKind::Url => "url",
+                    _ => "text",
+                }
+                autocomplete="off"
+                prop:value=value
+                on:input=move |ev| value.set(event_target_value(&ev))
+                class=class
+            />
+        }
+        .into_view(),
+    };
+    view! {
+        <div>
+            <label for=id class="block text-sm font-medium text-neutral-600">
+                {kind.label()}
+            </label>
+            {input}
+        </div>
+    }
+}
+
+/// The credential fields the sender filled in.
+fn filled_fields(fields: &[(FieldKind, RwSignal<String>)]) -> Vec<Field> {
+    fields
+        .iter()
+        .map(|(kind, value)| (*kind, value.get_untracked()))
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(kind, value)| Field {
+            kind,
+            value: Zeroizing::new(value),
+        })
+        .collect()
+}
+
+/// What the sender shares in zero-knowledge mode: a payload already in memory, or a file
+/// that is read while it is encrypted.
+enum Content {
+    Inline(Payload),
+    File(PickedFile),
+}
+
+/// A file picked for sharing. Its content is only read while it is encrypted.
+#[derive(Clone)]
+struct PickedFile {
+    name: String,
+    mime: String,
+    size: usize,
+    #[cfg(feature = "hydrate")]
+    file: web_sys::File,
+}
+
+/// The file picked in the file input, in the browser.
+fn pick_file(event: &Event) -> Result<Option<PickedFile>, SecretError> {
+    #[cfg(feature = "hydrate")]
+    {
+        let input = event_target::<web_sys::HtmlInputElement>(event);
+        let Some(file) = input.files().and_then(|files| files.get(0)) else {
+            return Ok(None);
+        };
+        if file.size() > MAX_FILE_LEN as f64 {
+            return Err(SecretError::TooLarge);
+        }
+        Ok(Some(PickedFile {
+            name: file.name(),
+            mime: file.type_(),
+            size: file.size() as usize,
+            file,
+        }))
+    }
+    #[cfg(not(feature = "hydrate"))]
+    {
+        let _ = event;
+        Ok(None)
+    }
+}
+
+/// Encrypts a picked file one segment at a time, as it is read, so its content is never
+/// held in memory in full.
+async fn seal_file(
+    id: &str,
+    file: &PickedFile,
+    passphrase: Option<&str>,
+    metadata: Metadata,
+) -> Result<crypto::Sealed, SecretError> {
+    let header = payload::file_header(&file.name, &file.mime);
+    let mut sealer = crypto::Sealer::new(
+        id,
+        header.len() + file.size,
+        passphrase,
+        metadata,
+        &crypto::DEFAULT_PADDING_BUCKETS,
+    )
+    .map_err(encryption_failed)?;
+    sealer.write(&header).map_err(encryption_failed)?;
+    #[cfg(feature = "hydrate")]
+    for start in (0..file.size).step_by(crypto::SEGMENT_LEN) {
+        let end = file.size.min(start + crypto::SEGMENT_LEN);
+        let read_failed = |_| SecretError::Unexpected("Could not read the file".into());
+        let slice = file
+            .file
+            .slice_with_f64_and_f64(start as f64, end as f64)
+            .map_err(read_failed)?;
+        let buffer = wasm_bindgen_futures::JsFuture::from(slice.array_buffer())
+            .await
+            .map_err(read_failed)?;
+        let bytes = Zeroizing::new(js_sys::Uint8Array::new(&buffer).to_vec());
+        sealer.write(&bytes).map_err(encryption_failed)?;
+    }
+    // Fails if the file changed size since it was picked.
+    sealer.finish().map_err(encryption_failed)
+}
+
+fn encryption_failed(e: crypto::Error) -> SecretError {
+    SecretError::Unexpected(format!("Encryption failed: {}", e))
+}
+
+/// Zero-knowledge mode: encrypts in the browser and uploads only the envelope.
+/// The key goes into the URL fragment, which browsers never send to the server.
+/// The id is chosen here, since the ciphertext is bound to it.
+async fn save_in_browser(
+    content: Content,
+    passphrase: String,
+    max_views: u32,
+    ttl: u64,
+) -> Result<SavedSecret, SecretError> {
     let id = Uuid::new_v4().to_string();
-    let keyencoded: String = general_purpose::URL_SAFE.encode(&key);
-    let keyandid = format!("{}::{}", id, keyencoded);
+    let metadata = Metadata::new(unix_now(), ttl, max_views);
+    let sealed = match content {
+        Content::Inline(payload) => crypto::seal(
+            &id,
+            &payload.encode(),
+            non_empty(&passphrase),
+            metadata,
+            &crypto::DEFAULT_PADDING_BUCKETS,
+        )
+        .map_err(encryption_failed)?,
+        Content::File(file) => seal_file(&id, &file, non_empty(&passphrase), metadata).await?,
+    };
+    let revoke_token = save_encrypted_secret(id.clone(), sealed.envelope.to_base64()).await?;
+    Ok(SavedSecret {
+        link: Zeroizing::new(share_link(&id, &sealed, LinkVersion::ZeroKnowledge).to_string()),
+        id,
+        revoke_token,
+    })
+}
 
-    let ciphertext = cipher
-        .encrypt(&nonce, token.as_ref())
-        .map_err(|e| ServerFnError::ServerError(format!("Encryption failed: {}", e)))?;
+/// Server functions take an empty passphrase to mean "no passphrase".
+fn non_empty(passphrase: &str) -> Option<&str> {
+    (!passphrase.is_empty()).then_some(passphrase)
+}
 
-    let nonce_secret: NoncedSecret = NoncedSecret {
-        nonce: nonce.to_vec(),
-        secret: ciphertext,
+fn share_link(id: &str, sealed: &crypto::Sealed, version: LinkVersion) -> ShareLink {
+    ShareLink {
+        id: id.to_string(),
+        key: Zeroizing::new(sealed.key.to_vec()),
+        version,
+        passphrase: sealed.envelope.kdf != Kdf::None,
+    }
+}
+
+#[server(SaveSecret, "/api")]
+pub async fn save_secret(
+    token: Zeroizing<String>,
+    passphrase: Zeroizing<String>,
+    max_views: u32,
+    ttl: u64,
+    dotenv: bool,
+) -> Result<SavedSecret, ServerFnError> {
+    if token.len() > MAX_SECRET_LEN {
+        return Err(SecretError::TooLarge.into());
+    }
+    let payload = if dotenv {
+        let vars = dotenv::parse(&token).map_err(|e| SecretError::InvalidRequest(e.to_string()))?;
+        Payload::Dotenv(vars)
+    } else {
+        Payload::Text(token)
+    };
+    Ok(save_on_server(payload, &passphrase, max_views, ttl)?)
+}
+
+/// Classic mode: encrypts `payload` on the server. The key goes into the path of the
+/// returned link.
+#[cfg(feature = "ssr")]
+pub(crate) fn save_on_server(
+    payload: Payload,
+    passphrase: &str,
+    max_views: u32,
+    ttl: u64,
+) -> Result<SavedSecret, SecretError> {
+    let too_large = match &payload {
+        Payload::Text(text) => text.len() > MAX_SECRET_LEN,
+        Payload::File(attachment) => attachment.data.len() > MAX_FILE_LEN,
+        _ => false,
     };
+    if too_large {
+        return Err(SecretError::TooLarge);
+    }
+    check_policy(max_views, ttl)?;
+    let id = Uuid::new_v4().to_string();
+    let metadata = Metadata::new(unix_now(), ttl, max_views);
+    let sealed = crypto::seal(
+        &id,
+        &payload.encode(),
+        non_empty(passphrase),
+        metadata,
+        &padding_buckets(),
+    )
+    .map_err(encryption_failed)?;
+    let link = Zeroizing::new(share_link(&id, &sealed, LinkVersion::Classic).to_string());
+    let mut envelope = sealed.envelope;
+    envelope.metadata.key_check = Some(crate::tokens::key_check(&id, sealed.key.as_ref()));
+
+    let revoke_token = store_secret(&id, envelope)?;
+
+    Ok(SavedSecret {
+        link,
+        id,
+        revoke_token,
+    })
+}
+
+/// Stores a secret that was already encrypted by the client, as a base64 envelope,
+/// under the id the client bound it to. The server never sees the key; it only checks
+/// the policy in the envelope metadata. Returns the revoke token.
+#[server(SaveEncryptedSecret, "/api")]
+pub async fn save_encrypted_secret(id: String, envelope: String) -> Result<String, ServerFnError> {
+    Uuid::parse_str(&id).map_err(|_| SecretError::InvalidRequest("Invalid id".into()))?;
+    let mut envelope = Envelope::from_base64(&envelope)
+        .map_err(|e| SecretError::InvalidRequest(format!("Invalid envelope: {}", e)))?;
+    // Zero-knowledge clients pad to the default buckets. The server cannot tell text
+    // from files, so the file limit applies to both.
+    let max_len = MAX_FILE_LEN + payload::MAX_OVERHEAD;
+    if envelope.ciphertext.len() > crypto::sealed_len(max_len, &crypto::DEFAULT_PADDING_BUCKETS) {
+        return Err(SecretError::TooLarge.into());
+    }
+    let metadata = &envelope.metadata;
+    check_policy(metadata.max_views.unwrap_or(0), metadata.ttl.unwrap_or(0))?;
+    let created_at = metadata.created_at.unwrap_or(0);
+    if unix_now().abs_diff(created_at) > MAX_CLOCK_SKEW {
+        return Err(SecretError::InvalidRequest("Invalid creation time".into()).into());
+    }
+    check_kdf(&envelope.kdf)?;
+    envelope.metadata.remaining_views = envelope.metadata.max_views;
+    envelope.metadata.failed_attempts = None;
+    envelope.metadata.last_failed_at = None;
+    envelope.metadata.key_check = None;
 
-    let store = spin_sdk::key_value::Store::open_default()
-        .map_err(|e| ServerFnError::ServerError(format!("Failed to open store: {}", e)))?;
+    Ok(store_secret(&id, envelope)?)
+}
+
+/// Padding buckets for secrets the server encrypts: the `padding_buckets` Spin variable
+/// (comma-separated sizes in bytes), or the defaults if it is unset or invalid.
+#[cfg(feature = "ssr")]
+fn padding_buckets() -> Vec<usize> {
+    let configured = spin_sdk::variables::get("padding_buckets")
+        .ok()
+        .and_then(|value| {
+            value
+                .split(',')
+                .map(|bucket| bucket.trim().parse::<usize>().ok().filter(|&b| b > 0))
+                .collect::<Option<Vec<_>>>()
+        });
+    match configured {
+        Some(mut buckets) => {
+            buckets.sort_unstable();
+            buckets
+        }
+        _ => crypto::DEFAULT_PADDING_BUCKETS.to_vec(),
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn check_policy(max_views: u32, ttl: u64) -> Result<(), SecretError> {
+    if !(MIN_TTL..=super::getsecret::MAX_TTL).contains(&ttl) {
+        return Err(SecretError::InvalidRequest("Invalid TTL".into()));
+    }
+    if max_views > MAX_VIEWS {
+        return Err(SecretError::InvalidRequest("Invalid view limit".into()));
+    }
+    Ok(())
+}
+
+/// Rejects client-chosen Argon2id parameters that would make the server (which derives
+/// the key when a link is opened in classic mode) do unreasonable work.
+#[cfg(feature = "ssr")]
+fn check_kdf(kdf: &Kdf) -> Result<(), SecretError> {
+    match kdf {
+        Kdf::None => Ok(()),
+        Kdf::Argon2id {
+            m_cost,
+            t_cost,
+            p_cost,
+            salt,
+        } if *m_cost <= 64 * 1024
+            && *t_cost <= 8
+            && *p_cost <= 4
+            && salt.len() == crypto::SALT_LEN =>
+        {
+            Ok(())
+        }
+        Kdf::Argon2id { .. } => Err(SecretError::InvalidRequest(
+            "Invalid key derivation parameters".into(),
+        )),
+    }
+}
+
+/// Writes a new record, refusing to overwrite an existing one, and returns the token
+/// that revokes it.
+#[cfg(feature = "ssr")]
+fn store_secret(id: &str, mut envelope: Envelope) -> Result<String, SecretError> {
+    let store = super::getsecret::open_store()?;
+
+    // The markers and status record of a burned or revoked secret outlive it, and a new
+    // secret under its id would inherit them.
+    let taken = [
+        id.to_string(),
+        super::secretstatus::status_key(id),
+        super::getsecret::viewed_key(id),
+        super::getsecret::revoked_key(id),
+    ]
+    .iter()
+    .any(|key| store.exists(key).unwrap_or(true));
+    if taken {
+        return Err(SecretError::InvalidRequest("Id already in use".into()));
+    }
 
-    store
-        .set_json(&id, &nonce_secret)
-        .map_err(|e| ServerFnError::ServerError(format!("Failed to set JSON in store: {}", e)))?;
+    let revoke_token = crate::tokens::generate_revoke_token();
+    let revoke_hash = crate::tokens::hash_revoke_token(&revoke_token);
+    // Written first, so a secret never exists without its status record.
+    super::secretstatus::record_created(&store, id, &envelope.metadata, revoke_hash.clone())?;
+    envelope.metadata.revoke_hash = Some(revoke_hash);
 
-    Ok(keyandid)
+    super::getsecret::write_envelope(&store, id, envelope)?;
+    Ok(revoke_token)
 }
@@ -0,0 +1,293 @@
+use crate::envelope::unix_now;
+#[cfg(feature = "ssr")]
+use crate::envelope::Metadata;
+use crate::error::SecretError;
+use leptos::*;
+use leptos_router::*;
+#[cfg(feature = "ssr")]
+use serde::{Deserialize, Serialize};
+use tokenshare_client::api::StatusReport;
+
+/// How many reveal times are kept for a secret without a view limit.
+#[cfg(feature = "ssr")]
+const MAX_LOGGED_VIEWS: usize = 100;
+
+/// How the status page sums up a report.
+fn state(report: &StatusReport, now: u64) -> &'static str {
+    if report.revoked_at.is_some() {
+        "Revoked"
+    } else if report.remaining_views == Some(0) {
+        "Viewed, no views left"
+    } else if report
+        .expires_at
+        .map_or(false, |expires_at| now >= expires_at)
+    {
+        "Expired"
+    } else {
+        "Active"
+    }
+}
+
+/// Kept in the store next to a secret, under `status:{id}`, and outlives it so the sender
+/// can still see when it was read. Holds the revoke token hash that guards the report.
+#[cfg(feature = "ssr")]
+#[derive(Serialize, Deserialize)]
+struct StatusRecord {
+    revoke_hash: Vec<u8>,
+    #[serde(flatten)]
+    report: StatusReport,
+}
+
+// Status page for the sender, authenticated by the revoke token in the URL fragment
+#[component]
+pub fn SecretStatus() -> impl IntoView {
+    let params = use_params_map();
+    let id = move || params.with(|params| params.get("id").cloned().unwrap_or_default());
+    let (report, set_report) = create_signal(None::<StatusReport>);
+    let (message, set_message) = create_signal("".to_string());
+    let refresh = move || {
+        spawn_local(async move {
+            match get_secret_status(id(), super::getsecret::fragment()).await {
+                Ok(status) => {
+                    set_message.set("".to_string());
+                    set_report.set(Some(status));
+                }
+                Err(e) => set_message.set(SecretError::from(e).to_string()),
+            }
+        });
+    };
+    // The token lives in the URL fragment, which only the browser sees.
+    create_effect(move |_| refresh());
+
+    let rows = move || report.get().map(report_rows);
+
+    view! {
+        <section>
+            <div class="relative items-center w-full px-5 py-12 mx-auto md:px-12 lg:px-24 max-w-7xl">
+                <div class="grid grid-cols-1">
+                    <div class="w-full max-w-lg mx-auto my-4 bg-white shadow-xl rounded-xl">
+                        <div class="p-6 lg:text-center">
+                            <a class="text-blue-600 text-medium flex" href="/">
+                                token.share
+                            </a>
+                            <h4 class="mt-8 text-2xl font-semibold leading-none tracking-tighter text-neutral-600 lg:text-3xl">
+                                Status of your secret
+                            </h4>
+                            <p class="mt-3 text-base leading-relaxed text-gray-500 break-all">
+                                {id}
+                            </p>
+                            <p class="mt-3 text-base text-gray-500">{message}</p>
+                            {rows}
+                            <button
+                                on:click=move |_| refresh()
+                                class="flex items-center justify-center w-full px-10 py-3 mt-6 text-base font-medium text-center text-white bg-blue-600 rounded-xl hover:bg-blue-700"
+                            >
+                                Refresh
+                            </button>
+                        </div>
+                    </div>
+                </div>
+            </div>
+        </section>
+    }
+}
+
+/// The fields of a status report, as a definition list.
+fn report_rows(report: StatusReport) -> impl IntoView {
+    let views = match (report.max_views, report.remaining_views) {
+        (Some(max_views), Some(remaining)) => {
+            format!(
+                "{} of {} (remaining {})",
+                report.views, max_views, remaining
+            )
+        }
+        _ => format!("{} (unlimited)", report.views),
+    };
+    let revealed_at = if report.revealed_at.is_empty() {
+        "Never".to_string()
+    } else {
+        report
+            .revealed_at
+            .iter()
+            .map(|at| format_time(*at))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    view! {
+        <dl class="mt-6 space-y-2 text-left text-base text-neutral-600">
+            <div>
+                <dt class="font-medium">State</dt>
+                <dd>{state(&report, unix_now())}</dd>
+            </div>
+            <div>
+                <dt class="font-medium">Created</dt>
+                <dd>{report.created_at.map(format_time)}</dd>
+            </div>
+            <div>
+                <dt class="font-medium">Expires</dt>
+                <dd>{report.expires_at.map(format_time)}</dd>
+            </div>
+            <div>
+                <dt class="font-medium">Views</dt>
+                <dd>{views}</dd>
+            </div>
+            <div>
+                <dt class="font-medium">Revealed at</dt>
+                <dd>{revealed_at}</dd>
+            </div>
+            <div>
+                <dt class="font-medium">Revoked</dt>
+                <dd>{report.revoked_at.map(format_time).unwrap_or_else(|| "No".to_string())}</dd>
+            </div>
+        </dl>
+    }
+}
+
+/// Formats a Unix time in the browser's locale and time zone.
+fn format_time(secs: u64) -> String {
+    #[cfg(feature = "hydrate")]
+    {
+        let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(secs as f64 * 1000.0));
+        date.to_locale_string("default", &wasm_bindgen::JsValue::UNDEFINED)
+            .into()
+    }
+    #[cfg(not(feature = "hydrate"))]
+    {
+        format!("{} (Unix time)", secs)
+    }
+}
+
+/// Returns the status of a secret to whoever holds its revoke token.
+#[server(GetSecretStatus, "/api", "Url", "secret_status")]
+pub async fn get_secret_status(
+    id: String,
+    revoke_token: String,
+) -> Result<StatusReport, ServerFnError> {
+    let store = super::getsecret::open_store()?;
+
+    match read_status(&store, &id)? {
+        Some(record) if record.revoke_hash == crate::tokens::hash_revoke_token(&revoke_token) => {
+            Ok(record.report)
+        }
+        _ => Err(SecretError::NotFound.into()),
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) const STATUS_PREFIX: &str = "status:";
+
+#[cfg(feature = "ssr")]
+pub(crate) fn status_key(id: &str) -> String {
+    format!("{}{}", STATUS_PREFIX, id)
+}
+
+#[cfg(feature = "ssr")]
+fn read_status(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+) -> Result<Option<StatusRecord>, SecretError> {
+    let bytes = store
+        .get(&status_key(id))
+        .map_err(|e| SecretError::storage("Failed to read from store", e))?;
+    bytes
+        .map(|bytes| serde_json::from_slice(&bytes))
+        .transpose()
+        .map_err(|e| SecretError::Unexpected(format!("Corrupt status record: {}", e)))
+}
+
+#[cfg(feature = "ssr")]
+fn write_status(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    record: &StatusRecord,
+) -> Result<(), SecretError> {
+    let bytes = serde_json::to_vec(record)
+        .map_err(|e| SecretError::Unexpected(format!("Failed to encode status: {}", e)))?;
+    store
+        .set(&status_key(id), &bytes)
+        .map_err(|e| SecretError::storage("Failed to write status", e))
+}
+
+/// Applies `update` to the status of `id`, if the secret has one.
+#[cfg(feature = "ssr")]
+fn update_status(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    update: impl FnOnce(&mut StatusReport),
+) -> Result<(), SecretError> {
+    let Some(mut record) = read_status(store, id)? else {
+        return Ok(());
+    };
+    update(&mut record.report);
+    write_status(store, id, &record)
+}
+
+/// Starts the status of a newly stored secret.
+#[cfg(feature = "ssr")]
+pub(crate) fn record_created(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    metadata: &Metadata,
+    revoke_hash: Vec<u8>,
+) -> Result<(), SecretError> {
+    let report = StatusReport {
+        created_at: metadata.created_at,
+        expires_at: metadata
+            .created_at
+            .zip(metadata.ttl)
+            .map(|(created_at, ttl)| created_at.saturating_add(ttl)),
+        max_views: metadata.max_views,
+        remaining_views: metadata.remaining_views,
+        ..StatusReport::default()
+    };
+    write_status(
+        store,
+        id,
+        &StatusRecord {
+            revoke_hash,
+            report,
+        },
+    )
+}
+
+/// Logs a reveal, with the number of views left after it.
+#[cfg(feature = "ssr")]
+pub(crate) fn record_view(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+    remaining_views: Option<u32>,
+) -> Result<(), SecretError> {
+    update_status(store, id, |report| {
+        report.views = report.views.saturating_add(1);
+        report.remaining_views = remaining_views;
+        report.revealed_at.push(unix_now());
+        if report.revealed_at.len() > MAX_LOGGED_VIEWS {
+            report.revealed_at.remove(0);
+        }
+    })
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) fn record_revoked(
+    store: &spin_sdk::key_value::Store,
+    id: &str,
+) -> Result<(), SecretError> {
+    update_status(store, id, |report| report.revoked_at = Some(unix_now()))
+}
+
+/// Whether the status record under `key` can be swept: it is kept as long as the
+/// "already viewed" markers, counted from when the secret expires.
+#[cfg(feature = "ssr")]
+pub(crate) fn is_stale(store: &spin_sdk::key_value::Store, key: &str, now: u64) -> bool {
+    let Some(id) = key.strip_prefix(STATUS_PREFIX) else {
+        return false;
+    };
+    read_status(store, id)
+        .ok()
+        .flatten()
+        .map_or(false, |record| {
+            record.report.expires_at.map_or(false, |expires_at| {
+                now >= expires_at.saturating_add(super::getsecret::MAX_TTL)
+            })
+        })
+}
@@ -0,0 +1,372 @@
+//! The `tokenshare` command-line client, built with the `cli` feature. Secrets are
+//! encrypted and decrypted here by [`Client`], with the same code as in the browser, so
+//! the server only ever sees envelopes.
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, ExitStatus};
+use tokenshare_client::dotenv;
+use tokenshare_client::link::ShareLink;
+use tokenshare_client::payload::{
+    Attachment, Payload, DEFAULT_FILE_NAME, MAX_FILE_LEN, MAX_SECRET_LEN,
+};
+use tokenshare_client::{Client, ClientError, SendOptions};
+use zeroize::Zeroizing;
+
+#[derive(Parser)]
+#[command(
+    name = "tokenshare",
+    version,
+    about = "Share secrets and tokens from the terminal"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encrypt standard input (or a file) and print a share link
+    Send(SendArgs),
+    /// Reveal the secret behind a share link
+    Get(GetArgs),
+    /// Run a command with the secret behind a share link in its environment
+    Exec(ExecArgs),
+}
+
+#[derive(Args)]
+struct SendArgs {
+    /// Server to store the secret on, e.g. https://tokenshare.example.com
+    #[arg(long, env = "TOKENSHARE_SERVER")]
+    server: String,
+    /// Share this file, under its name, instead of standard input
+    #[arg(long)]
+    file: Option<PathBuf>,
+    /// Check that the input is a dotenv bundle (KEY=value lines) and share its variables
+    #[arg(long, conflicts_with = "file")]
+    dotenv: bool,
+    /// How long the secret is kept: seconds, or a number with m, h or d (1h to 30d)
+    #[arg(long, default_value = "1d", value_parser = parse_ttl)]
+    ttl: u64,
+    /// How many times the secret can be viewed; 0 means until it expires
+    #[arg(long, default_value_t = 1)]
+    views: u32,
+    /// Also require this passphrase to reveal the secret
+    #[arg(long, env = "TOKENSHARE_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+}
+
+#[derive(Args)]
+struct GetArgs {
+    /// Share link, as printed by `send` or copied from the browser
+    url: String,
+    /// Passphrase, if the secret has one. For zero-knowledge links a wrong passphrase
+    /// still uses up a view.
+    #[arg(long, env = "TOKENSHARE_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    /// Write the secret to this file instead of standard output. Files are saved under
+    /// their own name in the current directory unless this is given.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ExecArgs {
+    /// Share link, as printed by `send` or copied from the browser
+    url: String,
+    /// Variable to set to a text secret. Dotenv bundles set their own variables.
+    #[arg(long, value_name = "NAME")]
+    env: Option<String>,
+    /// Passphrase, if the secret has one
+    #[arg(long, env = "TOKENSHARE_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    /// The command to run, and its arguments
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<OsString>,
+}
+
+pub fn run() -> ExitCode {
+    let cli = Cli::parse();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Could not start the runtime");
+    let result = runtime.and_then(|runtime| {
+        runtime.block_on(async {
+            match cli.command {
+                Command::Send(args) => send(args).await.map(|()| ExitCode::SUCCESS),
+                Command::Get(args) => get(args).await.map(|()| ExitCode::SUCCESS),
+                Command::Exec(args) => exec(args).await,
+            }
+        })
+    });
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Seals the input under a new key and stores only the envelope. The link, with the key
+/// in its fragment, goes to standard output; the status page link to standard error.
+async fn send(args: SendArgs) -> Result<()> {
+    let payload = read_payload(&args)?;
+    let options = SendOptions {
+        ttl: args.ttl,
+        max_views: args.views,
+        passphrase: args.passphrase.map(Zeroizing::new),
+    };
+    let shared = Client::new(&args.server).send(&payload, &options).await?;
+    println!("{}", shared.url.as_str());
+    eprintln!("Status and revocation: {}", shared.status_url);
+    Ok(())
+}
+
+/// The secret to send: the file given, or standard input as text (or as a file if it is
+/// not UTF-8).
+fn read_payload(args: &SendArgs) -> Result<Payload> {
+    if let Some(path) = &args.file {
+        let mut data = Zeroizing::new(Vec::new());
+        File::open(path)
+            .and_then(|file| file.take(MAX_FILE_LEN as u64 + 1).read_to_end(&mut data))
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        if data.len() > MAX_FILE_LEN {
+            bail!("Files can be at most 4 MiB");
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| DEFAULT_FILE_NAME.to_string());
+        return Ok(Payload::File(Attachment {
+            name,
+            mime: String::new(),
+            data,
+        }));
+    }
+
+    let mut input = Zeroizing::new(Vec::new());
+    std::io::stdin()
+        .lock()
+        .take(MAX_FILE_LEN as u64 + 1)
+        .read_to_end(&mut input)
+        .context("Could not read standard input")?;
+    match String::from_utf8(std::mem::take(&mut *input)) {
+        Ok(text) => {
+            let text = Zeroizing::new(text);
+            if text.len() > MAX_SECRET_LEN {
+                bail!("Text can be at most 64 KiB; share larger secrets with --file");
+            }
+            if text.trim().is_empty() {
+                bail!("Nothing to share: standard input is empty");
+            }
+            if args.dotenv {
+                return Ok(Payload::Dotenv(dotenv::parse(&text)?));
+            }
+            Ok(Payload::Text(text))
+        }
+        Err(e) => {
+            let data = Zeroizing::new(e.into_bytes());
+            if args.dotenv {
+                bail!("A dotenv bundle must be UTF-8 text");
+            }
+            if data.len() > MAX_FILE_LEN {
+                bail!("Files can be at most 4 MiB");
+            }
+            Ok(Payload::File(Attachment {
+                name: DEFAULT_FILE_NAME.to_string(),
+                mime: String::new(),
+                data,
+            }))
+        }
+    }
+}
+
+/// Reveals a secret: zero-knowledge links are decrypted here, classic links by the server.
+async fn get(args: GetArgs) -> Result<()> {
+    let passphrase = Zeroizing::new(args.passphrase.unwrap_or_default());
+    let payload = reveal(&args.url, &passphrase).await?;
+    write_payload(payload, args.output.as_deref())
+}
+
+/// Runs the command with the secret in its environment, and exits as it does. The
+/// secret is only handed to the child process: it is never printed or written to disk.
+/// The link's view is used up even if the command cannot be started.
+async fn exec(args: ExecArgs) -> Result<ExitCode> {
+    if let Some(name) = &args.env {
+        if !dotenv::is_valid_key(name) {
+            bail!("{} is not a valid variable name", name);
+        }
+    }
+    let passphrase = Zeroizing::new(args.passphrase.unwrap_or_default());
+    let vars = match (reveal(&args.url, &passphrase).await?, args.env) {
+        (Payload::Text(secret), Some(name)) => vec![(name, secret)],
+        (Payload::Text(_), None) => bail!("This secret is text; name its variable with --env"),
+        (Payload::Dotenv(vars), None) => vars.into_iter().map(|var| (var.key, var.value)).collect(),
+        (Payload::Dotenv(_), Some(_)) => {
+            bail!("This secret is a dotenv bundle, which names its own variables; leave out --env")
+        }
+        (Payload::File(_) | Payload::Credentials(_), _) => {
+            bail!("Only text secrets and dotenv bundles can be passed to a command")
+        }
+    };
+
+    let (program, program_args) = args.command.split_first().context("No command to run")?;
+    let status = std::process::Command::new(program)
+        .args(program_args)
+        .envs(vars.iter().map(|(name, value)| (name, value.as_str())))
+        .status()
+        .with_context(|| format!("Could not run {}", program.to_string_lossy()))?;
+    drop(vars);
+    Ok(exit_code(status))
+}
+
+/// The exit code to pass on for the command's status. A command killed by a signal
+/// exits as a shell would report it, with 128 plus the signal number.
+fn exit_code(status: ExitStatus) -> ExitCode {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return ExitCode::from(128u8.saturating_add(signal as u8));
+        }
+    }
+    match status.code() {
+        Some(code) => ExitCode::from(u8::try_from(code).unwrap_or(1)),
+        None => ExitCode::FAILURE,
+    }
+}
+
+/// Fetches and decrypts the secret behind `url`, from the server it points at.
+async fn reveal(url: &str, passphrase: &str) -> Result<Payload> {
+    let (server, _) = ShareLink::from_url(url)?;
+    match Client::new(server).reveal(url, passphrase).await {
+        Ok(payload) => Ok(payload),
+        Err(ClientError::PassphraseRequired) => bail!(
+            "This secret is protected by a passphrase; pass it with --passphrase or TOKENSHARE_PASSPHRASE"
+        ),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes text to standard output (or `output`), and files under their own name unless
+/// `output` is given. Never overwrites a file it was not pointed at.
+fn write_payload(payload: Payload, output: Option<&Path>) -> Result<()> {
+    let (bytes, name) = match payload {
+        Payload::Text(mut text) => (
+            Zeroizing::new(std::mem::take(&mut *text).into_bytes()),
+            None,
+        ),
+        Payload::Dotenv(vars) => {
+            let mut text = dotenv::to_dotenv(&vars);
+            (
+                Zeroizing::new(std::mem::take(&mut *text).into_bytes()),
+                None,
+            )
+        }
+        Payload::Credentials(fields) => {
+            let mut text = Zeroizing::new(String::new());
+            for field in fields {
+                text.push_str(&format!(
+                    "{}: {}\n",
+                    field.kind.label(),
+                    field.value.as_str()
+                ));
+            }
+            (
+                Zeroizing::new(std::mem::take(&mut *text).into_bytes()),
+                None,
+            )
+        }
+        Payload::File(attachment) => (attachment.data, Some(attachment.name)),
+    };
+    let (path, overwrite) = match (output, name) {
+        (Some(path), _) => (path.to_path_buf(), true),
+        (None, Some(name)) => {
+            // Only the last component, so a crafted name cannot write elsewhere.
+            let name = Path::new(&name)
+                .file_name()
+                .map_or_else(|| DEFAULT_FILE_NAME.into(), |name| name.to_owned());
+            (PathBuf::from(name), false)
+        }
+        (None, None) => {
+            return std::io::stdout()
+                .lock()
+                .write_all(&bytes)
+                .context("Could not write to standard output");
+        }
+    };
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(overwrite)
+        .create_new(!overwrite)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    eprintln!("Saved to {}", path.display());
+    Ok(())
+}
+
+/// Accepts seconds (`3600`) or a number with a unit (`30m`, `12h`, `7d`).
+fn parse_ttl(ttl: &str) -> Result<u64, String> {
+    let (number, unit) = match ttl.char_indices().last() {
+        Some((index, unit @ ('s' | 'm' | 'h' | 'd'))) => (&ttl[..index], unit),
+        _ => (ttl, 's'),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{} is not a duration like 3600, 30m, 12h or 7d", ttl))?;
+    let scale = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => 1,
+    };
+    Ok(number.saturating_mul(scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ttls_take_a_unit() {
+        assert_eq!(parse_ttl("3600"), Ok(3600));
+        assert_eq!(parse_ttl("90s"), Ok(90));
+        assert_eq!(parse_ttl("30m"), Ok(30 * 60));
+        assert_eq!(parse_ttl("12h"), Ok(12 * 60 * 60));
+        assert_eq!(parse_ttl("7d"), Ok(7 * 24 * 60 * 60));
+        assert_eq!(parse_ttl(&format!("{}d", u64::MAX)), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn malformed_ttls_are_rejected() {
+        for ttl in ["", "d", "-1h", "1.5h", "12w", "h12", " 1h", "1 h"] {
+            assert!(parse_ttl(ttl).is_err(), "{:?}", ttl);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commands_exit_codes_are_passed_on() {
+        use std::os::unix::process::ExitStatusExt;
+        // Raw wait statuses: the exit code sits in the second byte, a signal in the first.
+        assert_eq!(exit_code(ExitStatus::from_raw(0)), ExitCode::SUCCESS);
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), ExitCode::from(3));
+        assert_eq!(
+            exit_code(ExitStatus::from_raw(255 << 8)),
+            ExitCode::from(255)
+        );
+        assert_eq!(exit_code(ExitStatus::from_raw(9)), ExitCode::from(128 + 9));
+        assert_eq!(
+            exit_code(ExitStatus::from_raw(15)),
+            ExitCode::from(128 + 15)
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use leptos::ServerFnError;
+use std::fmt;
+
+/// Why a server function (or the client-side half of zero-knowledge mode) failed.
+///
+/// Server functions still return `ServerFnError`, so each case travels as
+/// `ServerFnError::ServerError("code")` or `("code: detail")` and is decoded again by the
+/// client. `Display` is the message shown to users.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretError {
+    NotFound,
+    Expired,
+    AlreadyViewed,
+    Revoked,
+    WrongPassphrase,
+    /// The link is malformed, or its key does not open the secret.
+    InvalidLink(String),
+    TooLarge,
+    RateLimited,
+    StorageUnavailable,
+    /// The one response hardened mode gives for every failed reveal.
+    Unavailable,
+    /// The request broke a rule, e.g. an out-of-range TTL or a malformed envelope.
+    InvalidRequest(String),
+    /// Anything else, including transport errors between the browser and the server.
+    Unexpected(String),
+}
+
+impl SecretError {
+    /// Stable identifier of the case, used on the wire.
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            SecretError::NotFound => "not_found",
+            SecretError::Expired => "expired",
+            SecretError::AlreadyViewed => "already_viewed",
+            SecretError::Revoked => "revoked",
+            SecretError::WrongPassphrase => "wrong_passphrase",
+            SecretError::InvalidLink(_) => "invalid_link",
+            SecretError::TooLarge => "too_large",
+            SecretError::RateLimited => "rate_limited",
+            SecretError::StorageUnavailable => "storage_unavailable",
+            SecretError::Unavailable => "unavailable",
+            SecretError::InvalidRequest(_) => "invalid_request",
+            SecretError::Unexpected(_) => "unexpected",
+        }
+    }
+
+    /// HTTP status the REST API answers with.
+    #[cfg(feature = "ssr")]
+    pub fn http_status(&self) -> u16 {
+        match self {
+            SecretError::NotFound | SecretError::Unavailable => 404,
+            SecretError::Expired | SecretError::AlreadyViewed | SecretError::Revoked => 410,
+            SecretError::WrongPassphrase => 403,
+            SecretError::InvalidLink(_) | SecretError::InvalidRequest(_) => 400,
+            SecretError::TooLarge => 413,
+            SecretError::RateLimited => 429,
+            SecretError::StorageUnavailable => 503,
+            SecretError::Unexpected(_) => 500,
+        }
+    }
+
+    fn detail(&self) -> Option<&str> {
+        match self {
+            SecretError::InvalidLink(detail)
+            | SecretError::InvalidRequest(detail)
+            | SecretError::Unexpected(detail) => Some(detail),
+            _ => None,
+        }
+    }
+
+    fn from_code(code: &str, detail: &str) -> Option<Self> {
+        Some(match code {
+            "not_found" => SecretError::NotFound,
+            "expired" => SecretError::Expired,
+            "already_viewed" => SecretError::AlreadyViewed,
+            "revoked" => SecretError::Revoked,
+            "wrong_passphrase" => SecretError::WrongPassphrase,
+            "invalid_link" => SecretError::InvalidLink(detail.to_string()),
+            "too_large" => SecretError::TooLarge,
+            "rate_limited" => SecretError::RateLimited,
+            "storage_unavailable" => SecretError::StorageUnavailable,
+            "unavailable" => SecretError::Unavailable,
+            "invalid_request" => SecretError::InvalidRequest(detail.to_string()),
+            "unexpected" => SecretError::Unexpected(detail.to_string()),
+            _ => return None,
+        })
+    }
+
+    /// A key-value store failure. The details are logged rather than sent to the client.
+    #[cfg(feature = "ssr")]
+    pub fn storage(context: &str, error: impl fmt::Display) -> Self {
+        eprintln!("{}: {}", context, error);
+        SecretError::StorageUnavailable
+    }
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::NotFound => write!(
+                f,
+                "This secret does not exist. It may have been deleted, or the link is wrong."
+            ),
+            SecretError::Expired => write!(f, "This secret has expired and no longer exists."),
+            SecretError::AlreadyViewed => write!(
+                f,
+                "This secret has already been viewed and no longer exists."
+            ),
+            SecretError::Revoked => write!(
+                f,
+                "This secret was revoked by its sender and no longer exists."
+            ),
+            SecretError::WrongPassphrase => write!(f, "Wrong passphrase, please try again."),
+            SecretError::InvalidLink(detail) => write!(f, "{}", detail),
+            SecretError::TooLarge => write!(f, "This secret is too large to share."),
+            SecretError::RateLimited => {
+                write!(f, "Too many attempts. Please wait a while and try again.")
+            }
+            SecretError::StorageUnavailable => write!(
+                f,
+                "Secrets cannot be stored or read right now. Please try again later."
+            ),
+            SecretError::Unavailable => write!(
+                f,
+                "This secret cannot be revealed. Check the link and passphrase; the secret may also have been viewed, expired or been revoked."
+            ),
+            SecretError::InvalidRequest(detail) => write!(f, "Invalid request: {}", detail),
+            SecretError::Unexpected(detail) => write!(f, "Something went wrong: {}", detail),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+impl From<SecretError> for ServerFnError {
+    fn from(error: SecretError) -> Self {
+        let message = match error.detail() {
+            Some(detail) => format!("{}: {}", error.code(), detail),
+            None => error.code().to_string(),
+        };
+        ServerFnError::ServerError(message)
+    }
+}
+
+impl From<ServerFnError> for SecretError {
+    fn from(error: ServerFnError) -> Self {
+        match error {
+            ServerFnError::ServerError(message) => {
+                let (code, detail) = message.split_once(": ").unwrap_or((&message, ""));
+                SecretError::from_code(code, detail)
+                    .unwrap_or_else(|| SecretError::Unexpected(message.clone()))
+            }
+            error => SecretError::Unexpected(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_case_round_trips_with_its_code_and_status() {
+        let cases = [
+            (SecretError::NotFound, "not_found", 404),
+            (SecretError::Expired, "expired", 410),
+            (SecretError::AlreadyViewed, "already_viewed", 410),
+            (SecretError::Revoked, "revoked", 410),
+            (SecretError::WrongPassphrase, "wrong_passphrase", 403),
+            (
+                SecretError::InvalidLink("Missing key".into()),
+                "invalid_link",
+                400,
+            ),
+            (SecretError::TooLarge, "too_large", 413),
+            (SecretError::RateLimited, "rate_limited", 429),
+            (SecretError::StorageUnavailable, "storage_unavailable", 503),
+            (SecretError::Unavailable, "unavailable", 404),
+            // Details may contain the separator themselves.
+            (
+                SecretError::InvalidRequest("ttl: out of range".into()),
+                "invalid_request",
+                400,
+            ),
+            (SecretError::Unexpected(String::new()), "unexpected", 500),
+        ];
+        for (error, code, status) in cases {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.http_status(), status, "{}", code);
+            assert_eq!(SecretError::from(ServerFnError::from(error.clone())), error);
+        }
+    }
+
+    #[test]
+    fn unknown_codes_are_kept_as_unexpected() {
+        assert_eq!(
+            SecretError::from(ServerFnError::ServerError("teapot: short and stout".into())),
+            SecretError::Unexpected("teapot: short and stout".into())
+        );
+        assert!(matches!(
+            SecretError::from(ServerFnError::Request("connection reset".into())),
+            SecretError::Unexpected(_)
+        ));
+    }
+}
@@ -1,8 +1,16 @@
 mod app;
 #[cfg(feature = "ssr")]
+mod api;
+mod error;
+#[cfg(feature = "ssr")]
+mod plain;
+#[cfg(feature = "ssr")]
 mod server;
+#[cfg(feature = "ssr")]
+mod tokens;
 
 use cfg_if::cfg_if;
+use tokenshare_client::{crypto, dotenv, envelope, link, payload};
 
 cfg_if! {
 if #[cfg(feature = "hydrate")] {
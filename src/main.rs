@@ -1,4 +1,17 @@
-#[cfg(feature = "ssr")]
-fn main() {
-    // Unused; required for cargo-leptos to build the front as it also wants to build a bin
-}
\ No newline at end of file
+use cfg_if::cfg_if;
+
+cfg_if! {
+if #[cfg(feature = "cli")] {
+
+    mod cli;
+
+    fn main() -> std::process::ExitCode {
+        cli::run()
+    }
+} else if #[cfg(feature = "ssr")] {
+
+    fn main() {
+        // Unused; required for cargo-leptos to build the front as it also wants to build a bin
+    }
+}
+}
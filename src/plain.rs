@@ -0,0 +1,243 @@
+//! Plain HTTP for scripts and `curl`, next to the web app:
+//!
+//! - `POST /` with the secret as the raw request body stores it in classic mode and
+//!   answers with the share URL as text. `?ttl=<seconds>` and `?views=<n>` set the policy,
+//!   and an `X-Passphrase` header adds a passphrase. Text longer than a text secret, or
+//!   bytes that are not UTF-8, are stored as a file.
+//! - `GET /get/{link}` answers with the raw secret when `Accept` prefers `text/plain` or
+//!   `application/octet-stream`, and with a [`RevealedSecret`] when it prefers
+//!   `application/json`. Anything else, `*/*` included, still gets the page, so link
+//!   previews and crawlers never use up a view.
+//!
+//! [`RevealedSecret`]: tokenshare_client::api::RevealedSecret
+
+use crate::api;
+use crate::dotenv;
+use crate::error::SecretError;
+use crate::payload::{Attachment, Payload, DEFAULT_FILE_NAME, MAX_FILE_LEN, MAX_SECRET_LEN};
+use spin_sdk::http::{IncomingRequest, Method, ResponseOutparam};
+use zeroize::Zeroizing;
+
+const GET_PREFIX: &str = "/get/";
+
+/// How a reveal is answered, from the request's `Accept` header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Html,
+    Raw,
+    Json,
+}
+
+type Response = (u16, Vec<(String, Vec<u8>)>, Zeroizing<Vec<u8>>);
+
+/// Whether [`handle`] answers `req` instead of the app.
+pub fn handles(req: &IncomingRequest) -> bool {
+    match req.method() {
+        Method::Post => path(req) == "/",
+        Method::Get => path(req).starts_with(GET_PREFIX) && format(req) != Format::Html,
+        _ => false,
+    }
+}
+
+pub async fn handle(req: IncomingRequest, resp_out: ResponseOutparam) {
+    let (format, result) = match req.method() {
+        Method::Post => (Format::Raw, create(req).await),
+        _ => {
+            let format = format(&req);
+            (format, reveal(&req, format).await)
+        }
+    };
+    let (status, headers, body) = result.unwrap_or_else(|error| failure(error, format));
+    api::send(resp_out, status, headers, Some(body)).await;
+}
+
+/// Stores the request body and answers with its share URL, plus the sender's status page
+/// in an `X-Status-Url` header.
+async fn create(req: IncomingRequest) -> Result<Response, SecretError> {
+    let origin = origin(&req)?;
+    let ttl = query_param(&req, "ttl")?.unwrap_or_else(tokenshare_client::api::default_ttl);
+    let max_views = query_param(&req, "views")?
+        .map_or_else(tokenshare_client::api::default_max_views, |views: u64| {
+            u32::try_from(views).unwrap_or(u32::MAX)
+        });
+    let passphrase = Zeroizing::new(api::header(&req, "x-passphrase").unwrap_or_default());
+    let body = api::read_body(req, MAX_FILE_LEN).await?;
+    if body.is_empty() {
+        return Err(SecretError::InvalidRequest(
+            "Nothing to share: the request body is empty".into(),
+        ));
+    }
+    let payload = match Payload::from_bytes(body) {
+        Payload::Text(mut text) if text.len() > MAX_SECRET_LEN => Payload::File(Attachment {
+            name: DEFAULT_FILE_NAME.to_string(),
+            mime: String::new(),
+            data: Zeroizing::new(std::mem::take(&mut *text).into_bytes()),
+        }),
+        payload => payload,
+    };
+
+    let saved = crate::app::savesecret::save_on_server(payload, &passphrase, max_views, ttl)?;
+    let url = Zeroizing::new(format!("{}/get/{}\n", origin, saved.link.as_str()));
+    let status_url = format!("{}/status/{}#{}", origin, saved.id, saved.revoke_token);
+    let headers = vec![
+        text_plain(),
+        ("x-status-url".to_string(), status_url.into_bytes()),
+    ];
+    Ok((201, headers, Zeroizing::new(url.as_bytes().to_vec())))
+}
+
+/// Reveals a classic secret as raw bytes or JSON.
+async fn reveal(req: &IncomingRequest, format: Format) -> Result<Response, SecretError> {
+    let path = path(req);
+    let link = path.strip_prefix(GET_PREFIX).unwrap_or_default();
+    // The key of a zero-knowledge link is in the fragment, which never reaches the server.
+    if !link.contains("::") {
+        return Err(SecretError::InvalidLink(
+            "Zero-knowledge secrets are decrypted by the client: open this link in a browser, or use `tokenshare get`.".into(),
+        ));
+    }
+    let passphrase = Zeroizing::new(api::header(req, "x-passphrase").unwrap_or_default());
+    let payload =
+        crate::app::getsecret::get_secret(Zeroizing::new(link.to_string()), passphrase).await?;
+
+    let mut headers = vec![
+        ("cache-control".to_string(), b"no-store".to_vec()),
+        ("x-content-type-options".to_string(), b"nosniff".to_vec()),
+    ];
+    let body = match (format, payload) {
+        (Format::Json, payload) => {
+            headers.push(application_json());
+            api::to_json(&api::revealed(payload)?)?
+        }
+        (_, Payload::Text(mut text)) => {
+            headers.push(text_plain());
+            Zeroizing::new(std::mem::take(&mut *text).into_bytes())
+        }
+        (_, Payload::Dotenv(vars)) => {
+            headers.push(text_plain());
+            let mut text = dotenv::to_dotenv(&vars);
+            Zeroizing::new(std::mem::take(&mut *text).into_bytes())
+        }
+        (_, Payload::File(attachment)) => {
+            let mime = if attachment.mime.is_empty() {
+                "application/octet-stream"
+            } else {
+                &attachment.mime
+            };
+            headers.push(("content-type".to_string(), mime.as_bytes().to_vec()));
+            headers.push((
+                "content-disposition".to_string(),
+                format!("attachment; filename=\"{}\"", header_safe(&attachment.name)).into_bytes(),
+            ));
+            attachment.data
+        }
+        (_, Payload::Credentials(_)) => {
+            return Err(SecretError::Unexpected("Unsupported secret".into()))
+        }
+    };
+    Ok((200, headers, body))
+}
+
+/// Errors in the format the client asked for: the API's JSON, or a line of text.
+fn failure(error: SecretError, format: Format) -> Response {
+    let status = error.http_status();
+    match format {
+        Format::Json => {
+            let body = api::error_body(error.code(), error.to_string()).unwrap_or_default();
+            (status, vec![application_json()], body)
+        }
+        _ => {
+            let body = Zeroizing::new(format!("{}\n", error).into_bytes());
+            (status, vec![text_plain()], body)
+        }
+    }
+}
+
+/// Picks the format the client prefers among those offered, by quality. Wildcards do not
+/// count, since browsers and link previews send them too.
+fn format(req: &IncomingRequest) -> Format {
+    let accept = api::header(req, "accept").unwrap_or_default();
+    let mut best = (Format::Html, 0.0);
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let format = match params
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "text/html" | "application/xhtml+xml" => Format::Html,
+            "text/plain" | "application/octet-stream" => Format::Raw,
+            "application/json" => Format::Json,
+            _ => continue,
+        };
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q=")?.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality > best.1 {
+            best = (format, quality);
+        }
+    }
+    best.0
+}
+
+/// The request path, without the query.
+fn path(req: &IncomingRequest) -> String {
+    let path = req.path_with_query().unwrap_or_default();
+    match path.split_once('?') {
+        Some((path, _)) => path.to_string(),
+        None => path,
+    }
+}
+
+/// A numeric query parameter, if present.
+fn query_param(req: &IncomingRequest, name: &str) -> Result<Option<u64>, SecretError> {
+    let path = req.path_with_query().unwrap_or_default();
+    let query = path.split_once('?').map_or("", |(_, query)| query);
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| {
+            value
+                .parse()
+                .map_err(|_| SecretError::InvalidRequest(format!("Invalid {}", name)))
+        })
+        .transpose()
+}
+
+/// Scheme and host the request was made to, from the full URL Spin passes along.
+fn origin(req: &IncomingRequest) -> Result<String, SecretError> {
+    let url = api::header(req, "spin-full-url")
+        .ok_or_else(|| SecretError::Unexpected("Request URL unknown".into()))?;
+    let host_start = url.find("://").map_or(0, |index| index + 3);
+    let host_end = url[host_start..]
+        .find('/')
+        .map_or(url.len(), |index| host_start + index);
+    Ok(url[..host_end].to_string())
+}
+
+/// A file name that can go between quotes in a header.
+fn header_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c == ' ' || (c.is_ascii_graphic() && c != '"' && c != '\\') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn text_plain() -> (String, Vec<u8>) {
+    (
+        "content-type".to_string(),
+        b"text/plain; charset=utf-8".to_vec(),
+    )
+}
+
+fn application_json() -> (String, Vec<u8>) {
+    ("content-type".to_string(), b"application/json".to_vec())
+}
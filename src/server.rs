@@ -9,7 +9,22 @@ async fn handle_tokenshare(req: IncomingRequest, resp_out: ResponseOutparam) {
     conf.leptos_options.output_name = "tokenshare".to_owned();
 
     crate::app::savesecret::SaveSecret::register_explicit().unwrap();
+    crate::app::savesecret::SaveEncryptedSecret::register_explicit().unwrap();
     crate::app::getsecret::GetSecret::register_explicit().unwrap();
+    crate::app::getsecret::GetEncryptedSecret::register_explicit().unwrap();
+    crate::app::getsecret::RevokeSecret::register_explicit().unwrap();
+    crate::app::getsecret::SweepExpired::register_explicit().unwrap();
+    crate::app::secretstatus::GetSecretStatus::register_explicit().unwrap();
+
+    if req
+        .path_with_query()
+        .map_or(false, |path| path.starts_with(crate::api::PREFIX))
+    {
+        return crate::api::handle(req, resp_out).await;
+    }
+    if crate::plain::handles(&req) {
+        return crate::plain::handle(req, resp_out).await;
+    }
 
     let app_fn = crate::app::App;
 
@@ -0,0 +1,30 @@
+//! Tokens the server hands out or checks, and the form they are stored in.
+
+use base64::{engine::general_purpose, Engine as _};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Bytes of randomness in a revoke token.
+const TOKEN_LEN: usize = 32;
+
+/// Generates the token that lets the sender revoke a secret before it is viewed.
+pub fn generate_revoke_token() -> String {
+    let mut token = [0u8; TOKEN_LEN];
+    OsRng.fill_bytes(&mut token);
+    general_purpose::URL_SAFE_NO_PAD.encode(token)
+}
+
+/// The form a revoke token is stored in, so a leaked store does not hand out tokens.
+pub fn hash_revoke_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// What the server keeps of the URL key of a secret it encrypted, to check links against
+/// before counting a wrong passphrase.
+pub fn key_check(id: &str, key: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"tokenshare key check\0");
+    hasher.update(id.as_bytes());
+    hasher.update(key);
+    hasher.finalize().to_vec()
+}